@@ -29,6 +29,12 @@ fuzz_target!(|data: &[u8]| {
         }
     }
 
+    // The syntax tree gives back the input exactly and lowers to the same script
+    if let Ok(tree) = parser::cst::SyntaxTree::parse(&input) {
+        assert_eq!(tree.to_string(), input, "Syntax tree is not lossless");
+        assert_eq!(Some(&tree.to_script()), result.as_ref().ok());
+    }
+
     // Test that the parser is deterministic - same input should produce same result
     let result2 = parser::parse(&input);
     match (result.is_ok(), result2.is_ok()) {
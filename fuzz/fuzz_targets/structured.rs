@@ -14,6 +14,7 @@ struct FuzzInput {
 struct FuzzCommand {
     condition: Option<String>,
     negated: bool,
+    may_fail: bool,
     background: bool,
     name: String,
     args: Vec<String>,
@@ -36,9 +37,11 @@ impl FuzzInput {
                 result.push_str(&format!("[{}] ", condition));
             }
 
-            // Add negation
+            // Add expectation prefix
             if cmd.negated {
                 result.push_str("! ");
+            } else if cmd.may_fail {
+                result.push_str("? ");
             }
 
             // Add command name and args
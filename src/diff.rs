@@ -0,0 +1,408 @@
+//! Line-based unified diffs for comparison errors
+//!
+//! Lines are matched with Myers' algorithm after trimming any common prefix
+//! and suffix. Under the `colors` feature, changed lines are colored and a
+//! removed line followed by its replacement has the changed words highlighted.
+
+/// Unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+
+/// Beyond this many edits, the differing middle is shown as replaced wholesale
+const MAX_EDIT_DISTANCE: usize = 2000;
+
+/// A step turning the old sequence into the new one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// Render a unified diff between two texts
+///
+/// `old_name` and `new_name` label the `---` and `+++` header lines. Texts
+/// whose lines match but which differ in trailing newlines get a note instead
+/// of hunks.
+pub(crate) fn unified_diff(old_name: &str, new_name: &str, old: &str, new: &str) -> String {
+    render(old_name, new_name, old, new, use_colors())
+}
+
+#[cfg(feature = "colors")]
+fn use_colors() -> bool {
+    use std::io::IsTerminal;
+    std::io::stderr().is_terminal()
+}
+
+#[cfg(not(feature = "colors"))]
+fn use_colors() -> bool {
+    false
+}
+
+fn render(old_name: &str, new_name: &str, old: &str, new: &str, color: bool) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = diff(&old_lines, &new_lines);
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    if ops.iter().all(|op| *op == Op::Equal) {
+        out.push_str("(lines are identical; the texts differ only in line endings)");
+        return out;
+    }
+
+    for hunk in hunks(&ops) {
+        out.push_str(&hunk_header(&ops, &hunk));
+        let (mut i, mut j) = positions(&ops[..hunk.start]);
+        let mut k = hunk.start;
+        while k < hunk.end {
+            match ops[k] {
+                Op::Equal => {
+                    out.push_str(&format!(" {}\n", old_lines[i]));
+                    i += 1;
+                    j += 1;
+                    k += 1;
+                }
+                _ => {
+                    // A run of changes: removals first, then additions
+                    let run_end = (k..hunk.end)
+                        .find(|&n| ops[n] == Op::Equal)
+                        .unwrap_or(hunk.end);
+                    let deleted = ops[k..run_end]
+                        .iter()
+                        .filter(|op| **op == Op::Delete)
+                        .count();
+                    let inserted = run_end - k - deleted;
+                    let removed = &old_lines[i..i + deleted];
+                    let added = &new_lines[j..j + inserted];
+                    render_change(&mut out, removed, added, color);
+                    i += deleted;
+                    j += inserted;
+                    k = run_end;
+                }
+            }
+        }
+    }
+
+    out.truncate(out.trim_end().len());
+    out
+}
+
+/// Render removed and added lines, highlighting changed words when colored
+fn render_change(out: &mut String, removed: &[&str], added: &[&str], color: bool) {
+    if !color {
+        for line in removed {
+            out.push_str(&format!("-{}\n", line));
+        }
+        for line in added {
+            out.push_str(&format!("+{}\n", line));
+        }
+        return;
+    }
+
+    // Pair lines up for word highlighting only when they correspond one-to-one
+    let paired = removed.len() == added.len();
+    for (n, line) in removed.iter().enumerate() {
+        let words = if paired {
+            highlight_words(line, added[n], Op::Delete)
+        } else {
+            line.to_string()
+        };
+        out.push_str(&format!("{}-{}{}\n", RED, words, RESET));
+    }
+    for (n, line) in added.iter().enumerate() {
+        let words = if paired {
+            highlight_words(removed[n], line, Op::Insert)
+        } else {
+            line.to_string()
+        };
+        out.push_str(&format!("{}+{}{}\n", GREEN, words, RESET));
+    }
+}
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const RESET: &str = "\x1b[0m";
+const REVERSE: &str = "\x1b[7m";
+const NO_REVERSE: &str = "\x1b[27m";
+
+/// Render one side of a changed line with the words unique to it reversed
+///
+/// `side` is [`Op::Delete`] to render `old`, or [`Op::Insert`] to render `new`.
+fn highlight_words(old: &str, new: &str, side: Op) -> String {
+    let old_words = split_words(old);
+    let new_words = split_words(new);
+    let ops = diff(&old_words, &new_words);
+
+    let words = if side == Op::Delete {
+        &old_words
+    } else {
+        &new_words
+    };
+    let mut out = String::new();
+    let mut index = 0;
+    for op in ops {
+        if op != Op::Equal && op != side {
+            continue;
+        }
+        let word = words[index];
+        index += 1;
+        if op == side && !word.trim().is_empty() {
+            out.push_str(&format!("{}{}{}", REVERSE, word, NO_REVERSE));
+        } else {
+            out.push_str(word);
+        }
+    }
+    out
+}
+
+/// Split a line into words, runs of whitespace, and single punctuation marks
+fn split_words(line: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let mut start = 0;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let same_class = |next: char| {
+            (is_word(c) && is_word(next)) || (c.is_whitespace() && next.is_whitespace())
+        };
+        match chars.peek() {
+            Some(&(_, next)) if same_class(next) => {}
+            _ => {
+                let end = i + c.len_utf8();
+                words.push(&line[start..end]);
+                start = end;
+            }
+        }
+    }
+    words
+}
+
+/// The old and new line positions after applying `ops`
+fn positions(ops: &[Op]) -> (usize, usize) {
+    let deleted = ops.iter().filter(|op| **op == Op::Delete).count();
+    let inserted = ops.iter().filter(|op| **op == Op::Insert).count();
+    let equal = ops.len() - deleted - inserted;
+    (equal + deleted, equal + inserted)
+}
+
+/// Group changes into hunks of ops, each with surrounding context
+fn hunks(ops: &[Op]) -> Vec<std::ops::Range<usize>> {
+    let mut hunks: Vec<std::ops::Range<usize>> = Vec::new();
+    for (k, op) in ops.iter().enumerate() {
+        if *op == Op::Equal {
+            continue;
+        }
+        let start = k.saturating_sub(CONTEXT_LINES);
+        let end = (k + 1 + CONTEXT_LINES).min(ops.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.end => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    hunks
+}
+
+/// Format the `@@ -l,s +l,s @@` header of a hunk
+fn hunk_header(ops: &[Op], hunk: &std::ops::Range<usize>) -> String {
+    let (old_start, new_start) = positions(&ops[..hunk.start]);
+    let (old_end, new_end) = positions(&ops[..hunk.end]);
+    let range = |start: usize, end: usize| {
+        let len = end - start;
+        // Empty ranges name the line before them, as in GNU diff
+        let first = if len == 0 { start } else { start + 1 };
+        format!("{},{}", first, len)
+    };
+    format!(
+        "@@ -{} +{} @@\n",
+        range(old_start, old_end),
+        range(new_start, new_end)
+    )
+}
+
+/// Compute the steps turning `a` into `b`
+fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Op> {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suffix = a[prefix..]
+        .iter()
+        .rev()
+        .zip(b[prefix..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+
+    let a_mid = &a[prefix..a.len() - suffix];
+    let b_mid = &b[prefix..b.len() - suffix];
+    let middle = myers(a_mid, b_mid).unwrap_or_else(|| {
+        let mut ops = vec![Op::Delete; a_mid.len()];
+        ops.extend(vec![Op::Insert; b_mid.len()]);
+        ops
+    });
+
+    let mut ops = vec![Op::Equal; prefix];
+    ops.extend(middle);
+    ops.extend(vec![Op::Equal; suffix]);
+    ops
+}
+
+/// Myers' shortest edit script, or `None` if it exceeds [`MAX_EDIT_DISTANCE`]
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Option<Vec<Op>> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = (n + m) as usize;
+    let offset = max as isize + 1;
+    let mut v = vec![0isize; 2 * max + 3];
+    // The furthest x reached on diagonals -d-1..=d+1, recorded after each step
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+    let snapshot =
+        |v: &[isize], d: isize| v[(offset - d - 1) as usize..=(offset + d + 1) as usize].to_vec();
+
+    'search: for d in 0..=max as isize {
+        if d as usize > MAX_EDIT_DISTANCE {
+            return None;
+        }
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                trace.push(snapshot(&v, d));
+                break 'search;
+            }
+        }
+        trace.push(snapshot(&v, d));
+    }
+
+    // Walk back from the end, one edit per step
+    let mut ops = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for d in (0..trace.len() as isize).rev() {
+        let k = x - y;
+        let (prev_x, prev_y) = if d == 0 {
+            (0, 0)
+        } else {
+            // Diagonal k of the previous step's snapshot is at index k + d
+            let v = &trace[d as usize - 1];
+            let down = k == -d || (k != d && v[(k - 1 + d) as usize] < v[(k + 1 + d) as usize]);
+            let prev_k = if down { k + 1 } else { k - 1 };
+            let prev_x = v[(prev_k + d) as usize];
+            (prev_x, prev_x - prev_k)
+        };
+        while x > prev_x && y > prev_y {
+            ops.push(Op::Equal);
+            x -= 1;
+            y -= 1;
+        }
+        if d > 0 {
+            ops.push(if x == prev_x { Op::Insert } else { Op::Delete });
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    ops.reverse();
+    Some(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_ops() {
+        let a = ["a", "b", "c", "d"];
+        let b = ["a", "x", "c", "d", "e"];
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                Op::Equal,
+                Op::Delete,
+                Op::Insert,
+                Op::Equal,
+                Op::Equal,
+                Op::Insert
+            ]
+        );
+        assert_eq!(diff::<&str>(&[], &[]), vec![]);
+        assert_eq!(diff(&["a"], &[]), vec![Op::Delete]);
+    }
+
+    #[test]
+    fn test_unified_diff_small_change() {
+        let old = "a\na\na\na\na\na\na\na\na\na";
+        let new = "b\na\na\na\na\na\na\na\na\na";
+        assert_eq!(
+            render("a", "b", old, new, false),
+            "--- a\n+++ b\n@@ -1,4 +1,4 @@\n-a\n+b\n a\n a\n a"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_separate_hunks() {
+        let old: Vec<String> = (1..=20).map(|i| i.to_string()).collect();
+        let mut new = old.clone();
+        new[1] = "two".to_string();
+        new[17] = "eighteen".to_string();
+
+        let diff = render("old", "new", &old.join("\n"), &new.join("\n"), false);
+        assert!(diff.contains("@@ -1,5 +1,5 @@\n 1\n-2\n+two\n 3\n 4\n 5\n"));
+        assert!(diff.contains("@@ -15,6 +15,6 @@\n 15\n 16\n 17\n-18\n+eighteen\n 19\n 20"));
+    }
+
+    #[test]
+    fn test_unified_diff_insertion_into_empty() {
+        assert_eq!(
+            render("old", "new", "", "x\ny", false),
+            "--- old\n+++ new\n@@ -0,0 +1,2 @@\n+x\n+y"
+        );
+    }
+
+    #[test]
+    fn test_unified_diff_line_endings_only() {
+        let diff = render("old", "new", "a\nb", "a\nb\n", false);
+        assert!(diff.ends_with("differ only in line endings)"));
+    }
+
+    #[test]
+    fn test_large_distance_falls_back() {
+        let old: Vec<String> = (0..3000).map(|i| format!("old {}", i)).collect();
+        let new: Vec<String> = (0..3000).map(|i| format!("new {}", i)).collect();
+        let ops = diff(&old, &new);
+        assert_eq!(ops.len(), 6000);
+        assert!(ops[..3000].iter().all(|op| *op == Op::Delete));
+    }
+
+    #[test]
+    fn test_split_words() {
+        assert_eq!(
+            split_words("let x = foo(bar);"),
+            vec!["let", " ", "x", " ", "=", " ", "foo", "(", "bar", ")", ";"]
+        );
+        assert_eq!(split_words(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_highlight_words() {
+        let removed = highlight_words("the quick fox", "the slow fox", Op::Delete);
+        assert_eq!(removed, format!("the {}quick{} fox", REVERSE, NO_REVERSE));
+        let added = highlight_words("the quick fox", "the slow fox", Op::Insert);
+        assert_eq!(added, format!("the {}slow{} fox", REVERSE, NO_REVERSE));
+    }
+
+    #[test]
+    fn test_colored_diff_highlights_words() {
+        let diff = render("old", "new", "a\nvalue = 1\nb", "a\nvalue = 2\nb", true);
+        assert!(diff.contains(&format!(
+            "{}-value = {}1{}{}",
+            RED, REVERSE, NO_REVERSE, RESET
+        )));
+        assert!(diff.contains(&format!(
+            "{}+value = {}2{}{}",
+            GREEN, REVERSE, NO_REVERSE, RESET
+        )));
+    }
+}
@@ -12,6 +12,7 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 /// Main error type for testscript operations
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Regex error
     #[error("Regex error: {0}")]
@@ -32,6 +33,10 @@ pub enum Error {
     #[error("Command '{command}' failed: {message}")]
     Command { command: String, message: String },
 
+    /// A command was invoked incorrectly, e.g. with the wrong arguments
+    #[error("Invalid use of '{command}': {message}")]
+    Usage { command: String, message: String },
+
     /// File comparison error
     #[error("File comparison failed: {message}")]
     FileCompare { message: String },
@@ -40,6 +45,17 @@ pub enum Error {
     #[error("{}", format_output_comparison(.expected, .actual))]
     OutputCompare { expected: String, actual: String },
 
+    /// A command ran past its deadline and was killed
+    #[error("{}", format_timeout(.command, .timeout, .stdout, .stderr))]
+    Timeout {
+        command: String,
+        timeout: std::time::Duration,
+        /// Output captured before the command was killed
+        stdout: String,
+        /// Error output captured before the command was killed
+        stderr: String,
+    },
+
     /// Unknown command error
     #[error("Unknown command: {command}")]
     UnknownCommand { command: String },
@@ -48,18 +64,51 @@ pub enum Error {
     #[error("Unknown condition: {condition}")]
     UnknownCondition { condition: String },
 
+    /// A `${VAR:?message}` expansion found `VAR` unset or empty
+    #[error("{name}: {message}")]
+    UnsetVariable { name: String, message: String },
+
     /// Generic error with message
     #[error("{0}")]
     Generic(String),
 
     /// Script execution error with context
-    #[error("Error in {script_file} at line {line_num}:\n{context}\n\n{source}")]
+    #[error("Error in {script_file} at line {line_num}:\n{context}\n\n{source}{}", format_last_command(.last_command))]
     ScriptError {
         script_file: String,
         line_num: usize,
         context: String,
         #[source]
         source: Box<Error>,
+        /// What the last command run before the failure did, if any
+        last_command: Option<String>,
+    },
+
+    /// A script failure together with the script's execution log
+    #[error("{source}\n\nScript log:\n{}", .log.trim_end())]
+    WithLog {
+        /// The log of the commands run up to the failure
+        log: String,
+        #[source]
+        source: Box<Error>,
+    },
+
+    /// One or more scripts failed in a run
+    #[error("{}", format_scripts_failed(.failures, *.passed, *.skipped))]
+    ScriptsFailed {
+        /// Each failed script's path with its error
+        failures: Vec<(String, Error)>,
+        /// Number of scripts that passed
+        passed: usize,
+        /// Number of scripts that were skipped
+        skipped: usize,
+    },
+
+    /// Several commands failed in one script (continue-on-failure mode)
+    #[error("{} failures in {script_file}:\n\n{}", .failures.len(), format_failures(.failures))]
+    MultipleFailures {
+        script_file: String,
+        failures: Vec<Error>,
     },
 }
 
@@ -80,6 +129,31 @@ impl Error {
         }
     }
 
+    /// Create a usage error for a malformed command invocation
+    pub fn usage_error(command: impl Into<String>, message: impl Into<String>) -> Self {
+        Error::Usage {
+            command: command.into(),
+            message: message.into(),
+        }
+    }
+
+    /// Whether a `!` prefix may turn this error into success
+    ///
+    /// Only genuine assertion and command failures can be negated. Malformed
+    /// invocations, unknown commands or conditions, invalid regexes,
+    /// timeouts and `${VAR:?message}` expansions fail the script regardless, as in Go's testscript.
+    pub fn is_negatable(&self) -> bool {
+        !matches!(
+            self,
+            Error::Usage { .. }
+                | Error::UnknownCommand { .. }
+                | Error::UnknownCondition { .. }
+                | Error::Regex(_)
+                | Error::Timeout { .. }
+                | Error::UnsetVariable { .. }
+        )
+    }
+
     /// Create a script error with context
     pub fn script_error(
         script_file: impl Into<String>,
@@ -93,8 +167,96 @@ impl Error {
             line_num,
             context,
             source: Box::new(source),
+            last_command: None,
+        }
+    }
+
+    /// Attach a description of the last command run before a script error
+    ///
+    /// Other errors are returned unchanged.
+    pub fn with_last_command(mut self, description: Option<String>) -> Self {
+        if let Error::ScriptError { last_command, .. } = &mut self {
+            *last_command = description;
+        }
+        self
+    }
+
+    /// The script line where this error occurred, if known
+    ///
+    /// For several failures this is the line of the first one.
+    pub fn line_num(&self) -> Option<usize> {
+        match self {
+            Error::Parse { line, .. } => Some(*line),
+            Error::ScriptError { line_num, .. } => Some(*line_num),
+            Error::WithLog { source, .. } => source.line_num(),
+            Error::MultipleFailures { failures, .. } => {
+                failures.iter().find_map(|failure| failure.line_num())
+            }
+            _ => None,
+        }
+    }
+
+    /// Combine the failures collected from one script
+    ///
+    /// A single failure is returned as-is rather than wrapped.
+    pub fn multiple_failures(script_file: impl Into<String>, mut failures: Vec<Error>) -> Self {
+        if failures.len() == 1 {
+            return failures.remove(0);
+        }
+        Error::MultipleFailures {
+            script_file: script_file.into(),
+            failures,
+        }
+    }
+}
+
+/// Format a list of errors, numbered and separated by blank lines
+fn format_failures(failures: &[Error]) -> String {
+    failures
+        .iter()
+        .enumerate()
+        .map(|(i, failure)| format!("[{}] {}", i + 1, failure))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Format the description of the last command, set off from the error
+fn format_last_command(last_command: &Option<String>) -> String {
+    match last_command {
+        Some(description) => format!("\n\n{}", description),
+        None => String::new(),
+    }
+}
+
+/// Format a timeout with whatever output the command produced before it
+fn format_timeout(
+    command: &str,
+    timeout: &std::time::Duration,
+    stdout: &str,
+    stderr: &str,
+) -> String {
+    let mut message = format!("Command '{}' timed out after {:?}", command, timeout);
+    for (name, output) in [("stdout", stdout), ("stderr", stderr)] {
+        if !output.is_empty() {
+            message.push_str(&format!("\n[{}]\n{}", name, output.trim_end()));
         }
     }
+    message
+}
+
+/// Format the failed scripts of a run followed by a summary line
+fn format_scripts_failed(failures: &[(String, Error)], passed: usize, skipped: usize) -> String {
+    let mut message = String::new();
+    for (script, error) in failures {
+        message.push_str(&format!("Test '{}' failed: {}\n\n", script, error));
+    }
+    message.push_str(&format!(
+        "{} passed, {} failed, {} skipped",
+        passed,
+        failures.len(),
+        skipped
+    ));
+    message
 }
 
 /// Generate error context showing surrounding lines
@@ -135,7 +297,19 @@ fn generate_error_context(script_content: &str, error_line: usize) -> String {
 }
 
 /// Format output comparison error for better readability
+///
+/// Multi-line outputs are shown as a unified diff.
 fn format_output_comparison(expected: &str, actual: &str) -> String {
+    if !expected.is_empty()
+        && !actual.is_empty()
+        && (expected.contains('\n') || actual.contains('\n'))
+    {
+        return format!(
+            "Output mismatch:\n{}",
+            crate::diff::unified_diff("Expected", "Actual", expected, actual)
+        );
+    }
+
     #[cfg(feature = "colors")]
     {
         if std::io::stderr().is_terminal() {
@@ -427,6 +601,66 @@ mod tests {
         assert!(context.is_empty());
     }
 
+    #[test]
+    fn test_multiple_failures() {
+        let single = Error::multiple_failures("test.txt", vec![Error::Generic("one".into())]);
+        assert_eq!(single.to_string(), "one");
+
+        let multiple = Error::multiple_failures(
+            "test.txt",
+            vec![Error::Generic("one".into()), Error::Generic("two".into())],
+        );
+        assert_eq!(
+            multiple.to_string(),
+            "2 failures in test.txt:\n\n[1] one\n\n[2] two"
+        );
+    }
+
+    #[test]
+    fn test_timeout_display() {
+        let err = Error::Timeout {
+            command: "sleep 10".to_string(),
+            timeout: std::time::Duration::from_millis(500),
+            stdout: "partial\n".to_string(),
+            stderr: String::new(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Command 'sleep 10' timed out after 500ms\n[stdout]\npartial"
+        );
+    }
+
+    #[test]
+    fn test_line_num() {
+        let source = Error::command_error("test", "failed");
+        let script_err = Error::script_error("test.txt", 2, "line1\nline2", source);
+        assert_eq!(script_err.line_num(), Some(2));
+
+        let multiple = Error::multiple_failures(
+            "test.txt",
+            vec![Error::Generic("no line".into()), script_err],
+        );
+        assert_eq!(multiple.line_num(), Some(2));
+
+        assert_eq!(Error::Generic("no line".into()).line_num(), None);
+    }
+
+    #[test]
+    fn test_scripts_failed_display() {
+        let err = Error::ScriptsFailed {
+            failures: vec![
+                ("a.txt".to_string(), Error::Generic("first".into())),
+                ("b.txt".to_string(), Error::Generic("second".into())),
+            ],
+            passed: 3,
+            skipped: 1,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Test 'a.txt' failed: first\n\nTest 'b.txt' failed: second\n\n3 passed, 2 failed, 1 skipped"
+        );
+    }
+
     #[test]
     fn test_output_compare_error_display() {
         let err = Error::OutputCompare {
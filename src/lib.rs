@@ -6,19 +6,83 @@
 //! This crate provides a framework for writing integration tests for CLI tools
 //! using `.txtar` format files that contain both test scripts and file contents.
 
+mod diff;
 pub mod error;
 pub mod parser;
+pub mod report;
 pub mod run;
 
 pub use error::{Error, Result};
-pub use parser::{Command, Script, TxtarFile};
-pub use run::{CommandFn, RunParams, SetupFn, TestEnvironment};
+pub use parser::{condition::Condition, Command, Comment, Expectation, Script, TxtarFile};
+pub use report::{Outcome, RunReport, ScriptReport};
+pub use run::{CommandFn, ConditionFn, RunParams, ScriptOutcome, SetupFn, TestEnvironment};
 
 // Re-export for advanced users who need direct access
 pub use run::run_test;
 
 // Internal function used by the Builder - not part of public API
-fn run(params: &mut RunParams, test_data_glob: &str) -> Result<()> {
+fn run(params: &RunParams, test_data_glob: &str) -> Result<RunReport> {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    let test_files = discover_test_files(params, test_data_glob)?;
+
+    // Each script gets its own isolated TestEnvironment, so scripts can run
+    // on several worker threads. Workers pull the next unclaimed script and
+    // store its result in that script's slot, keeping the report in sorted
+    // order regardless of completion order.
+    let next = AtomicUsize::new(0);
+    let slots: Vec<Mutex<Option<ScriptReport>>> =
+        test_files.iter().map(|_| Mutex::new(None)).collect();
+    let workers = params.worker_count().min(test_files.len());
+
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::SeqCst);
+                let Some(test_file) = test_files.get(index) else {
+                    break;
+                };
+                let report = run_one(test_file, params);
+                *slots[index].lock().unwrap() = Some(report);
+            });
+        }
+    });
+
+    let scripts = slots
+        .into_iter()
+        .map(|slot| slot.into_inner().unwrap().expect("every script is run"))
+        .collect();
+    Ok(RunReport { scripts })
+}
+
+// Run a single script and record its outcome
+fn run_one(test_file: &std::path::Path, params: &RunParams) -> ScriptReport {
+    let start = std::time::Instant::now();
+    let result = run::run_script(test_file, params);
+    let duration = start.elapsed();
+
+    let (outcome, error) = match result {
+        Ok(ScriptOutcome::Passed) => (Outcome::Passed, None),
+        Ok(ScriptOutcome::Skipped { reason }) => (Outcome::Skipped { reason }, None),
+        Ok(ScriptOutcome::Stopped { reason }) => (Outcome::Stopped { reason }, None),
+        Err(e) => (Outcome::Failed, Some(e)),
+    };
+
+    ScriptReport {
+        path: test_file.to_path_buf(),
+        outcome,
+        duration,
+        failing_line: error.as_ref().and_then(|e| e.line_num()),
+        error,
+    }
+}
+
+// Find the script files to run, sorted for a consistent execution order
+fn discover_test_files(
+    params: &RunParams,
+    test_data_glob: &str,
+) -> Result<Vec<std::path::PathBuf>> {
     use walkdir::WalkDir;
 
     let mut test_files = Vec::new();
@@ -102,13 +166,7 @@ fn run(params: &mut RunParams, test_data_glob: &str) -> Result<()> {
         }
     }
 
-    // Run each test file
-    for test_file in test_files {
-        run::run_script(&test_file, params)
-            .map_err(|e| Error::Generic(format!("Test '{}' failed: {}", test_file.display(), e)))?;
-    }
-
-    Ok(())
+    Ok(test_files)
 }
 
 /// Builder for configuring and running testscript tests
@@ -123,9 +181,14 @@ fn run(params: &mut RunParams, test_data_glob: &str) -> Result<()> {
 /// - **Network condition**: `[net]` - Tests network connectivity by pinging reliable hosts
 /// - **Build conditions**: `[debug]`, `[release]` - Based on compilation flags
 /// - **Program conditions**: `[exec:program]` - Checks if a program is available in PATH
-/// - **Environment conditions**: `[env:VAR]` - Dynamic checking of environment variables
+/// - **Environment conditions**: `[env:VAR]` - Whether the script's environment has the
+///   variable, including those set with `env` and leaving out the host's in hermetic mode
 /// - **Program existence**: `[exec:program]` - Checks if a program is available in PATH
+/// - **Parameterized conditions**: `[prefix:argument]` - Decided by providers added with
+///   [`Builder::condition_fn`], such as `[feature:serde]` or `[kernel>=5.10]`
 /// - **Negation**: Use `!` to negate any condition, e.g. `[!windows]`, `[!env:CI]`, `[!exec:git]`
+/// - **Combination**: Several brackets must all hold, e.g. `[linux] [!exec:git]`, and one
+///   bracket may combine conditions with `&&`, `||` and parentheses, e.g. `[unix && (net || env:CI)]`
 ///
 /// ## Examples
 ///
@@ -230,7 +293,7 @@ impl Builder {
     /// ```
     pub fn setup<F>(mut self, func: F) -> Self
     where
-        F: Fn(&mut TestEnvironment) -> Result<()> + 'static,
+        F: Fn(&mut TestEnvironment) -> Result<()> + Send + Sync + 'static,
     {
         self.params = self.params.setup(func);
         self
@@ -260,7 +323,7 @@ impl Builder {
     /// - `unix`, `windows`, `linux`, `darwin` - Platform detection
     /// - `debug`, `release` - Build type  
     /// - `exec:program` - Program availability (35+ programs)
-    /// - `env:VAR` - Environment variables (dynamic)
+    /// - `env:VAR` - Variables in the script's environment (dynamic)
     ///
     /// # Examples
     /// ```no_run
@@ -282,6 +345,69 @@ impl Builder {
         self
     }
 
+    /// Add a provider for parameterized conditions such as `[feature:serde]`
+    ///
+    /// A condition whose name starts with `prefix` calls `func` with the rest
+    /// of the name, without a leading `:`, and the environment of the running
+    /// script. The prefix must be followed by the end of the name or a
+    /// character that can't continue a word, so `kernel` also provides
+    /// `[kernel>=5.10]` (with the argument `>=5.10`). The provider is called
+    /// each time the condition is evaluated; use
+    /// [`pure_condition_fn`](Self::pure_condition_fn) for checks that only
+    /// depend on the argument. An error from the provider fails the script.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use testscript_rs::testscript;
+    ///
+    /// testscript::run("testdata")
+    ///     .condition_fn("port-free", |port, _env| {
+    ///         let port: u16 = port.parse().map_err(|_| {
+    ///             testscript_rs::Error::Generic(format!("Invalid port: {}", port))
+    ///         })?;
+    ///         Ok(std::net::TcpListener::bind(("127.0.0.1", port)).is_ok())
+    ///     })
+    ///     .condition_fn("var-set", |name, env| Ok(env.env_vars.contains_key(name)))
+    ///     .execute()
+    ///     .unwrap();
+    /// ```
+    pub fn condition_fn<F>(mut self, prefix: &str, func: F) -> Self
+    where
+        F: Fn(&str, &TestEnvironment) -> Result<bool> + Send + Sync + 'static,
+    {
+        self.params = self.params.condition_fn(prefix, func);
+        self
+    }
+
+    /// Add a provider for parameterized conditions whose result depends only
+    /// on the argument
+    ///
+    /// This works like [`condition_fn`](Self::condition_fn), except that the
+    /// provider is called at most once per argument in a run, and every
+    /// script reuses the result, which suits expensive checks.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use testscript_rs::testscript;
+    ///
+    /// testscript::run("testdata")
+    ///     .pure_condition_fn("feature", |feature, _env| {
+    ///         Ok(match feature {
+    ///             "serde" => cfg!(feature = "serde"),
+    ///             _ => false,
+    ///         })
+    ///     })
+    ///     .execute()
+    ///     .unwrap();
+    /// ```
+    pub fn pure_condition_fn<F>(mut self, prefix: &str, func: F) -> Self
+    where
+        F: Fn(&str, &TestEnvironment) -> Result<bool> + Send + Sync + 'static,
+    {
+        self.params = self.params.pure_condition_fn(prefix, func);
+        self
+    }
+
     /// Enable or disable updating test scripts with actual output
     ///
     /// When enabled, instead of failing on output mismatches, the test files
@@ -397,16 +523,193 @@ impl Builder {
         self
     }
 
+    /// Run scripts in a hermetic environment instead of inheriting the host's
+    ///
+    /// By default, commands inherit the full environment of the test process,
+    /// so variables like `HOME`, `XDG_*`, `LANG` or `RUST_LOG` can make scripts
+    /// behave differently between machines. In hermetic mode the host
+    /// environment is cleared and each script starts from a fixed baseline:
+    ///
+    /// - `WORK` - the script's working directory
+    /// - `HOME=/no-home`
+    /// - `TMPDIR=$WORK/.tmp` (created for each script)
+    /// - `PATH` - copied from the host so programs can still be found
+    ///
+    /// Use [`Builder::passthrough_env`] to let specific host variables through,
+    /// and [`Builder::setup`] to set anything else.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use testscript_rs::testscript;
+    ///
+    /// testscript::run("testdata")
+    ///     .hermetic(true)
+    ///     .passthrough_env(["CARGO_TARGET_DIR"])
+    ///     .execute()
+    ///     .unwrap();
+    /// ```
+    pub fn hermetic(mut self, hermetic: bool) -> Self {
+        self.params = self.params.hermetic(hermetic);
+        self
+    }
+
+    /// Pass specific host environment variables through in hermetic mode
+    ///
+    /// Variables that are not set on the host are ignored. This has no effect
+    /// unless [`Builder::hermetic`] is enabled.
+    pub fn passthrough_env<I, S>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.params = self.params.passthrough_env(vars);
+        self
+    }
+
+    /// Keep executing a script after a command fails
+    ///
+    /// This matches Go testscript's `-continue` flag. Instead of stopping at the
+    /// first failing command, every command in the script runs, and all
+    /// failures are reported together (each with its line number and context)
+    /// once the script finishes. `skip` still ends the script immediately.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use testscript_rs::testscript;
+    ///
+    /// testscript::run("testdata")
+    ///     .continue_on_failure(true)
+    ///     .execute()
+    ///     .unwrap();
+    /// ```
+    pub fn continue_on_failure(mut self, continue_on_failure: bool) -> Self {
+        self.params = self.params.continue_on_failure(continue_on_failure);
+        self
+    }
+
+    /// Set how many scripts run concurrently
+    ///
+    /// Each script already runs in its own isolated working directory, so
+    /// scripts can safely run on several threads. Defaults to the number of
+    /// available CPUs; use `parallel(1)` to run scripts one at a time.
+    /// Results are always reported in sorted script order.
+    ///
+    /// Setup functions and custom commands are shared between threads, so
+    /// they must not rely on process-global state such as the current
+    /// directory.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use testscript_rs::testscript;
+    ///
+    /// testscript::run("testdata")
+    ///     .parallel(4)
+    ///     .execute()
+    ///     .unwrap();
+    /// ```
+    pub fn parallel(mut self, workers: usize) -> Self {
+        self.params = self.params.parallel(workers);
+        self
+    }
+
+    /// Set the maximum time a single command may run
+    ///
+    /// This applies to `exec` and to `wait` for background processes. When the
+    /// timeout expires the process, along with any processes it started in its
+    /// process group on Unix, is killed. The script then fails with an error
+    /// naming the line that timed out and the output captured so far.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use testscript_rs::testscript;
+    ///
+    /// testscript::run("testdata")
+    ///     .command_timeout(Duration::from_secs(30))
+    ///     .script_timeout(Duration::from_secs(120))
+    ///     .execute()
+    ///     .unwrap();
+    /// ```
+    pub fn command_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.params = self.params.command_timeout(timeout);
+        self
+    }
+
+    /// Set the maximum time a whole script may run
+    ///
+    /// Any command still running when the script's deadline passes is killed,
+    /// and no further commands are started. A script can override this with
+    /// the `timeout` directive, e.g. `timeout 5m` at the top of the script.
+    pub fn script_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.params = self.params.script_timeout(timeout);
+        self
+    }
+
+    /// Print the execution log of every script
+    ///
+    /// The log follows Go's testscript format: each `#` comment line starts a
+    /// section, shown with the time it took, and commands are echoed as
+    /// `> command` followed by their `[stdout]`, `[stderr]` and
+    /// `[exit status N]`. Without verbose mode only the headers and the
+    /// failing section are kept, and that log is attached to the error of a
+    /// failed script.
+    ///
+    /// Verbose mode can also be enabled by setting `TESTSCRIPT_VERBOSE=1`.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use testscript_rs::testscript;
+    ///
+    /// testscript::run("testdata").verbose(true).execute().unwrap();
+    /// ```
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.params = self.params.verbose(verbose);
+        self
+    }
+
     /// Execute all test scripts in the configured directory
     ///
     /// This will discover all `.txt` files in the directory and run them as test scripts.
     /// Each test runs in isolation with its own temporary directory.
     ///
+    /// Every script is run even if an earlier one fails. Scripts that end
+    /// with `skip` are reported on stderr and do not count as failures.
+    ///
     /// # Returns
-    /// `Ok(())` if all tests pass, or the first error encountered.
-    pub fn execute(mut self) -> Result<()> {
+    /// `Ok(())` if all tests pass, or an [`Error::ScriptsFailed`] listing each
+    /// failed script with its error, followed by a summary line.
+    pub fn execute(self) -> Result<()> {
+        let report = self.execute_with_report()?;
+
+        for script in &report.scripts {
+            if let Outcome::Skipped { reason } = &script.outcome {
+                eprintln!("Test '{}' skipped: {}", script.path.display(), reason);
+            }
+        }
+
+        report.into_result()
+    }
+
+    /// Execute all test scripts and return a report of each script's outcome
+    ///
+    /// Unlike [`Builder::execute`], failing scripts do not produce an error.
+    /// Instead each script gets a [`ScriptReport`] with its path, outcome,
+    /// duration, and for failures the failing line and error. An error is only
+    /// returned if the scripts could not be discovered.
+    ///
+    /// # Examples
+    /// ```no_run
+    /// use testscript_rs::{testscript, Outcome};
+    ///
+    /// let report = testscript::run("testdata").execute_with_report().unwrap();
+    /// for script in &report.scripts {
+    ///     println!("{}: {:?} in {:?}", script.path.display(), script.outcome, script.duration);
+    /// }
+    /// assert!(report.is_success());
+    /// ```
+    pub fn execute_with_report(self) -> Result<RunReport> {
         let pattern = format!("{}/*.txt", self.dir);
-        run(&mut self.params, &pattern)
+        run(&self.params, &pattern)
     }
 }
 
@@ -2,6 +2,12 @@
 
 use crate::error::{Error, Result};
 
+pub mod condition;
+pub mod cst;
+
+use condition::Condition;
+use cst::{CommandLine, Span, SyntaxTree};
+
 /// Represents a single file block in the txtar archive
 #[derive(Debug, Clone, PartialEq)]
 pub struct TxtarFile {
@@ -11,28 +17,63 @@ pub struct TxtarFile {
     pub contents: Vec<u8>,
 }
 
+/// What outcome a command must have for the script to pass
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Expectation {
+    /// The command must succeed (no prefix)
+    #[default]
+    Success,
+    /// The command must fail (`!` prefix)
+    Failure,
+    /// The command may succeed or fail (`?` prefix)
+    Either,
+}
+
 /// Represents a single command line in the script
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub struct Command {
     /// The command name (first word)
     pub name: String,
     /// Command arguments
     pub args: Vec<String>,
+    /// Command arguments as environment expansion templates
+    ///
+    /// These match `args` except that any `$` inside single quotes is escaped
+    /// as `$$`, so expanding a template yields the final argument while
+    /// single-quoted text is kept literal.
+    pub arg_templates: Vec<String>,
     /// Line number in the original script (for error reporting)
     pub line_num: usize,
-    /// Optional condition prefix (e.g., "windows", "linux")
-    pub condition: Option<String>,
-    /// Whether this is a background command (ends with &)
+    /// Optional condition prefix, such as `[windows]` or `[linux] [!exec:git]`
+    ///
+    /// Several bracketed conditions are combined with `&&`.
+    pub condition: Option<Condition>,
+    /// Whether this is a background command (ends with `&` or `&name&`)
     pub background: bool,
-    /// Whether this command should be negated (starts with !)
-    pub negated: bool,
+    /// The name given with `&name&`, if any
+    pub background_name: Option<String>,
+    /// Whether the command must succeed, must fail (`!`) or may do either (`?`)
+    pub expectation: Expectation,
+}
+
+/// A comment line in the script, which starts a new section of the log
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comment {
+    /// The comment text, including the leading `#`
+    pub text: String,
+    /// Line number in the original script
+    pub line_num: usize,
 }
 
 /// Represents the parsed script and its associated files
 #[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
 pub struct Script {
     /// List of commands to execute
     pub commands: Vec<Command>,
+    /// Comment lines in the script section, in order
+    pub comments: Vec<Comment>,
     /// List of files to create in the test environment
     pub files: Vec<TxtarFile>,
 }
@@ -43,6 +84,8 @@ pub struct Script {
 /// 1. Commands and comments at the top
 /// 2. File blocks starting with "-- filename --" headers
 ///
+/// Use [`cst::SyntaxTree`] instead to keep the exact text of the script.
+///
 /// # Arguments
 /// * `content` - The raw content of the .txtar file
 ///
@@ -52,77 +95,11 @@ pub struct Script {
 /// # Errors
 /// Returns ParseError if the content is malformed
 pub fn parse(content: &str) -> Result<Script> {
-    let mut commands = Vec::new();
-    let mut files = Vec::new();
-    let lines: Vec<&str> = content.lines().collect();
-
-    let mut i = 0;
-    let mut current_file: Option<(String, Vec<u8>)> = None;
-
-    while i < lines.len() {
-        let line = lines[i];
-        let line_num = i + 1; // 1-based line numbering
-
-        // Skip empty lines
-        if line.trim().is_empty() {
-            i += 1;
-            continue;
-        }
-
-        // Check for file header: -- filename --
-        if let Some(filename) = parse_file_header(line) {
-            // Save previous file if any
-            if let Some((name, mut contents)) = current_file.take() {
-                // Remove trailing newline if present (since we add one for every line)
-                if contents.ends_with(b"\n") {
-                    contents.pop();
-                }
-                files.push(TxtarFile { name, contents });
-            }
-
-            // Start new file
-            current_file = Some((filename, Vec::new()));
-            i += 1;
-            continue;
-        }
-
-        // If we're inside a file block, add content to the current file
-        if let Some((_, ref mut contents)) = current_file {
-            // Add the line plus newline to file contents
-            contents.extend_from_slice(line.as_bytes());
-            contents.push(b'\n');
-            i += 1;
-            continue;
-        }
-
-        // Skip comment lines (outside of file blocks)
-        if line.trim_start().starts_with('#') {
-            i += 1;
-            continue;
-        }
-
-        // Parse command line
-        if let Some(command) = parse_command_line(line, line_num)? {
-            commands.push(command);
-        }
-
-        i += 1;
-    }
-
-    // Save final file if any
-    if let Some((name, mut contents)) = current_file.take() {
-        // Remove trailing newline if present (since we add one for every line)
-        if contents.ends_with(b"\n") {
-            contents.pop();
-        }
-        files.push(TxtarFile { name, contents });
-    }
-
-    Ok(Script { commands, files })
+    Ok(SyntaxTree::parse(content)?.to_script())
 }
 
 /// Parse a file header line like "-- filename --"
-fn parse_file_header(line: &str) -> Option<String> {
+pub(crate) fn parse_file_header(line: &str) -> Option<String> {
     let trimmed = line.trim();
     if trimmed.starts_with("-- ") && trimmed.ends_with(" --") && trimmed.len() > 6 {
         let filename = &trimmed[3..trimmed.len() - 3];
@@ -133,77 +110,164 @@ fn parse_file_header(line: &str) -> Option<String> {
 }
 
 /// Parse a command line into a Command struct
+#[cfg(test)]
 fn parse_command_line(line: &str, line_num: usize) -> Result<Option<Command>> {
+    Ok(parse_command_syntax(line, line_num)?.map(|syntax| syntax.command))
+}
+
+/// Parse a command line, keeping the spans of its parts within the line
+pub(crate) fn parse_command_syntax(line: &str, line_num: usize) -> Result<Option<CommandLine>> {
     let trimmed = line.trim();
 
     // Skip empty lines and comments
     if trimmed.is_empty() || trimmed.starts_with('#') {
         return Ok(None);
     }
-
-    // Check for condition prefix like [windows] or [!unix]
-    let (condition, command_part) = if trimmed.starts_with('[') {
-        if let Some(end_bracket) = trimmed.find(']') {
-            let condition_str = &trimmed[1..end_bracket];
-            let remaining = trimmed[end_bracket + 1..].trim();
-            (Some(condition_str.to_string()), remaining)
-        } else {
+    let trimmed_start = offset_in(line, trimmed);
+
+    // Check for condition prefixes like [windows] or [!unix] [exec:git]
+    let mut condition: Option<Condition> = None;
+    let mut condition_span = None;
+    let mut command_part = trimmed;
+    while let Some(rest) = command_part.strip_prefix('[') {
+        let Some(end_bracket) = rest.find(']') else {
             return Err(Error::parse_error(line_num, "Unclosed condition bracket"));
-        }
-    } else {
-        (None, trimmed)
-    };
+        };
+        let parsed = Condition::parse(&rest[..end_bracket], line_num)?;
+        condition = Some(match condition {
+            Some(previous) => Condition::And(Box::new(previous), Box::new(parsed)),
+            None => parsed,
+        });
+        let end = offset_in(line, rest) + end_bracket + 1;
+        condition_span = Some(Span::new(trimmed_start, end));
+        command_part = rest[end_bracket + 1..].trim();
+    }
 
     // Parse the command and arguments
-    let tokens = parse_command_tokens(command_part)?;
+    let part_start = offset_in(line, command_part);
+    let tokens = tokenize(command_part)?;
     if tokens.is_empty() {
         return Ok(None);
     }
+    let span = |token: &Token| Span::new(part_start + token.start, part_start + token.end);
 
-    // Check for negation (command starts with !)
-    let (negated, command_name, args_start_idx) = if tokens[0] == "!" {
-        if tokens.len() < 2 {
-            return Err(Error::parse_error(line_num, "! requires a command"));
-        }
-        (true, tokens[1].clone(), 2)
+    // Check for an expectation prefix (! or ?)
+    let expectation = match tokens[0].text.as_str() {
+        "!" => Expectation::Failure,
+        "?" => Expectation::Either,
+        _ => Expectation::Success,
+    };
+    let args_start_idx = if expectation == Expectation::Success {
+        1
+    } else if tokens.len() < 2 {
+        return Err(Error::parse_error(
+            line_num,
+            format!("{} requires a command", tokens[0].text),
+        ));
     } else {
-        (false, tokens[0].clone(), 1)
+        2
     };
-
-    let mut args: Vec<String> = tokens.into_iter().skip(args_start_idx).collect();
-
-    // Check for background command (ends with &)
-    let background = if let Some(last_arg) = args.last() {
-        if last_arg == "&" {
-            args.pop(); // Remove the & from args
-            true
-        } else {
-            false
+    let prefix = (args_start_idx == 2).then(|| span(&tokens[0]));
+    let name = span(&tokens[args_start_idx - 1]);
+    let command_name = tokens[args_start_idx - 1].text.clone();
+
+    let mut arg_spans: Vec<Span> = tokens[args_start_idx..].iter().map(span).collect();
+    let (mut args, mut arg_templates): (Vec<String>, Vec<String>) = tokens
+        .into_iter()
+        .skip(args_start_idx)
+        .map(|token| (token.text, token.template))
+        .unzip();
+
+    // Check for background command (ends with & or &name&)
+    let (background, background_name) = match args.last().map(String::as_str) {
+        Some("&") => (true, None),
+        Some(last_arg)
+            if last_arg.len() > 2 && last_arg.starts_with('&') && last_arg.ends_with('&') =>
+        {
+            (true, Some(last_arg[1..last_arg.len() - 1].to_string()))
         }
-    } else {
-        false
+        _ => (false, None),
     };
+    let mut background_span = None;
+    if background {
+        // Remove the marker from args
+        args.pop();
+        arg_templates.pop();
+        background_span = arg_spans.pop();
+    }
 
-    Ok(Some(Command {
-        name: command_name,
-        args,
-        line_num,
-        condition,
-        background,
-        negated,
+    Ok(Some(CommandLine {
+        command: Command {
+            name: command_name,
+            args,
+            arg_templates,
+            line_num,
+            condition,
+            background,
+            background_name,
+            expectation,
+        },
+        condition: condition_span,
+        prefix,
+        name,
+        args: arg_spans,
+        background: background_span,
     }))
 }
 
+/// The byte offset of `inner`, a slice of `outer`, within it
+fn offset_in(outer: &str, inner: &str) -> usize {
+    inner.as_ptr() as usize - outer.as_ptr() as usize
+}
+
+/// A single word of a command line
+struct Token {
+    /// The word with quotes and escapes resolved
+    text: String,
+    /// The word as an expansion template (`$` inside single quotes becomes `$$`)
+    template: String,
+    /// Where the word starts in the input, including any opening quote
+    start: usize,
+    /// Where the word ends in the input
+    end: usize,
+}
+
 /// Parse command tokens, handling quoted arguments
+#[cfg(test)]
 fn parse_command_tokens(input: &str) -> Result<Vec<String>> {
+    Ok(tokenize(input)?
+        .into_iter()
+        .map(|token| token.text)
+        .collect())
+}
+
+/// Split a command line into tokens, tracking which text was single-quoted
+fn tokenize(input: &str) -> Result<Vec<Token>> {
     let mut tokens = Vec::new();
     let mut current_token = String::new();
+    let mut current_template = String::new();
     let mut in_quotes = false;
     let mut quote_char = '"';
     let mut just_closed_quotes = false;
-    let mut chars = input.chars().peekable();
+    let mut token_start = None;
+    let mut chars = input.char_indices();
+
+    // Push a character to both the token and its template, protecting `$`
+    // from expansion when it appears inside single quotes
+    let push = |token: &mut String, template: &mut String, ch: char, literal: bool| {
+        token.push(ch);
+        if literal && ch == '$' {
+            template.push_str("$$");
+        } else {
+            template.push(ch);
+        }
+    };
 
-    while let Some(ch) = chars.next() {
+    while let Some((index, ch)) = chars.next() {
+        let literal = in_quotes && quote_char == '\'';
+        if !matches!(ch, ' ' | '\t') || in_quotes {
+            token_start.get_or_insert(index);
+        }
         match ch {
             '"' | '\'' => {
                 if in_quotes && ch == quote_char {
@@ -217,57 +281,61 @@ fn parse_command_tokens(input: &str) -> Result<Vec<String>> {
                     just_closed_quotes = false;
                 } else {
                     // Different quote type inside quotes - treat as literal
-                    current_token.push(ch);
+                    push(&mut current_token, &mut current_template, ch, literal);
                     just_closed_quotes = false;
                 }
             }
             ' ' | '\t' => {
                 if in_quotes {
-                    current_token.push(ch);
+                    push(&mut current_token, &mut current_template, ch, literal);
                     just_closed_quotes = false;
                 } else if !current_token.is_empty() || just_closed_quotes {
-                    tokens.push(current_token.clone());
-                    current_token.clear();
+                    tokens.push(Token {
+                        text: std::mem::take(&mut current_token),
+                        template: std::mem::take(&mut current_template),
+                        start: token_start.take().unwrap_or(index),
+                        end: index,
+                    });
                     just_closed_quotes = false;
                 }
             }
             '\\' => {
                 // Handle escape sequences
-                if let Some(next_ch) = chars.next() {
-                    if in_quotes && quote_char == '\'' {
+                if let Some((_, next_ch)) = chars.next() {
+                    let resolved = if literal {
                         // In single quotes, only process \\ and \'
                         match next_ch {
-                            '\\' => current_token.push('\\'),
-                            '\'' => current_token.push('\''),
-                            'n' => current_token.push('\n'), // Still process \n in single quotes for Go compat
-                            't' => current_token.push('\t'), // Still process \t in single quotes for Go compat
-                            'r' => current_token.push('\r'), // Still process \r in single quotes for Go compat
-                            _ => {
-                                current_token.push('\\');
-                                current_token.push(next_ch);
-                            }
+                            '\\' => Some('\\'),
+                            '\'' => Some('\''),
+                            'n' => Some('\n'), // Still process \n in single quotes for Go compat
+                            't' => Some('\t'), // Still process \t in single quotes for Go compat
+                            'r' => Some('\r'), // Still process \r in single quotes for Go compat
+                            _ => None,
                         }
                     } else {
                         // In double quotes or outside quotes, process all escapes
                         match next_ch {
-                            'n' => current_token.push('\n'),
-                            't' => current_token.push('\t'),
-                            'r' => current_token.push('\r'),
-                            '\\' => current_token.push('\\'),
-                            '"' => current_token.push('"'),
-                            '\'' => current_token.push('\''),
-                            _ => {
-                                current_token.push('\\');
-                                current_token.push(next_ch);
-                            }
+                            'n' => Some('\n'),
+                            't' => Some('\t'),
+                            'r' => Some('\r'),
+                            '\\' => Some('\\'),
+                            '"' => Some('"'),
+                            '\'' => Some('\''),
+                            _ => None,
                         }
+                    };
+                    if let Some(resolved) = resolved {
+                        push(&mut current_token, &mut current_template, resolved, literal);
+                    } else {
+                        push(&mut current_token, &mut current_template, '\\', literal);
+                        push(&mut current_token, &mut current_template, next_ch, literal);
                     }
                 } else {
-                    current_token.push('\\');
+                    push(&mut current_token, &mut current_template, '\\', literal);
                 }
             }
             _ => {
-                current_token.push(ch);
+                push(&mut current_token, &mut current_template, ch, literal);
                 just_closed_quotes = false;
             }
         }
@@ -275,7 +343,12 @@ fn parse_command_tokens(input: &str) -> Result<Vec<String>> {
 
     // Add final token if any (including empty tokens that were quoted)
     if !current_token.is_empty() || just_closed_quotes {
-        tokens.push(current_token);
+        tokens.push(Token {
+            text: current_token,
+            template: current_template,
+            start: token_start.unwrap_or(input.len()),
+            end: input.len(),
+        });
     }
 
     Ok(tokens)
@@ -333,23 +406,65 @@ mod tests {
         assert_eq!(cmd.line_num, 1);
         assert_eq!(cmd.condition, None);
         assert!(!cmd.background);
-        assert!(!cmd.negated);
+        assert_eq!(cmd.expectation, Expectation::Success);
 
         let cmd = parse_command_line("[windows] exec echo hello", 2)
             .unwrap()
             .unwrap();
-        assert_eq!(cmd.condition, Some("windows".to_string()));
+        assert_eq!(cmd.condition, Some(Condition::Name("windows".to_string())));
+
+        let cmd = parse_command_line("[linux] [!exec:git || env:CI] exec git status", 2)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cmd.condition.unwrap().to_string(),
+            "linux && (!exec:git || env:CI)"
+        );
+        assert_eq!(cmd.name, "exec");
+        assert_eq!(cmd.args, vec!["git", "status"]);
 
         let cmd = parse_command_line("exec echo hello &", 3).unwrap().unwrap();
         assert!(cmd.background);
+        assert_eq!(cmd.background_name, None);
         assert_eq!(cmd.args, vec!["echo", "hello"]);
 
+        let cmd = parse_command_line("exec server --port 80 &web&", 3)
+            .unwrap()
+            .unwrap();
+        assert!(cmd.background);
+        assert_eq!(cmd.background_name, Some("web".to_string()));
+        assert_eq!(cmd.args, vec!["server", "--port", "80"]);
+
         let cmd = parse_command_line("! exists missing_file", 4)
             .unwrap()
             .unwrap();
-        assert!(cmd.negated);
+        assert_eq!(cmd.expectation, Expectation::Failure);
         assert_eq!(cmd.name, "exists");
         assert_eq!(cmd.args, vec!["missing_file"]);
+
+        let cmd = parse_command_line("? exec rm -rf cache", 5)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cmd.expectation, Expectation::Either);
+        assert_eq!(cmd.name, "exec");
+        assert_eq!(cmd.args, vec!["rm", "-rf", "cache"]);
+
+        assert!(parse_command_line("?", 6).is_err());
+    }
+
+    #[test]
+    fn test_arg_templates_protect_single_quotes() {
+        let cmd = parse_command_line("exec echo $HOME '$HOME' \"$HOME\" a'$b'$c", 1)
+            .unwrap()
+            .unwrap();
+        assert_eq!(cmd.args, vec!["echo", "$HOME", "$HOME", "$HOME", "a$b$c"]);
+        assert_eq!(
+            cmd.arg_templates,
+            vec!["echo", "$HOME", "$$HOME", "$HOME", "a$$b$c"]
+        );
+
+        let cmd = parse_command_line("exec sleep 1 &", 2).unwrap().unwrap();
+        assert_eq!(cmd.arg_templates, vec!["sleep", "1"]);
     }
 
     #[test]
@@ -406,4 +521,33 @@ second file
             b"first file\ncontent\nsecond file"
         );
     }
+
+    #[test]
+    fn test_parse_comments() {
+        let content = r#"# section one
+exec echo hello
+  # section two
+stdout hello
+
+-- notes.txt --
+# not a comment
+"#;
+
+        let script = parse(content).unwrap();
+
+        assert_eq!(
+            script.comments,
+            vec![
+                Comment {
+                    text: "# section one".to_string(),
+                    line_num: 1,
+                },
+                Comment {
+                    text: "# section two".to_string(),
+                    line_num: 3,
+                },
+            ]
+        );
+        assert_eq!(script.commands.len(), 2);
+    }
 }
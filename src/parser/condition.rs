@@ -0,0 +1,340 @@
+//! Condition expressions for command prefixes
+//!
+//! A command may start with several bracketed conditions, as in
+//! `[linux] [!exec:git] cmd`, and only runs when all of them hold. Inside a
+//! bracket, conditions combine with `!`, `&&`, `||` and parentheses, where
+//! `!` binds tightest and `&&` binds tighter than `||`.
+
+use crate::error::{Error, Result};
+use std::fmt;
+
+/// A parsed condition expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// A named condition such as `unix`, `net` or `exec:git`
+    Name(String),
+    /// Holds when the inner condition doesn't
+    Not(Box<Condition>),
+    /// Holds when both conditions hold
+    And(Box<Condition>, Box<Condition>),
+    /// Holds when either condition holds
+    Or(Box<Condition>, Box<Condition>),
+}
+
+impl Condition {
+    /// Parse the text between a condition's brackets
+    pub(crate) fn parse(text: &str, line_num: usize) -> Result<Self> {
+        let tokens = tokenize(text).map_err(|message| Error::parse_error(line_num, message))?;
+        if tokens.is_empty() {
+            return Err(Error::parse_error(line_num, "Empty condition"));
+        }
+        let mut parser = Parser { tokens, pos: 0 };
+        let condition = parser.or().and_then(|condition| match parser.peek() {
+            None => Ok(condition),
+            Some(token) => Err(format!("Unexpected '{}'", token)),
+        });
+        condition.map_err(|message| {
+            Error::parse_error(line_num, format!("{} in condition '[{}]'", message, text))
+        })
+    }
+
+    /// Every condition name the expression refers to, in order
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_names(&mut names);
+        names
+    }
+
+    fn collect_names<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Condition::Name(name) => names.push(name),
+            Condition::Not(inner) => inner.collect_names(names),
+            Condition::And(left, right) | Condition::Or(left, right) => {
+                left.collect_names(names);
+                right.collect_names(names);
+            }
+        }
+    }
+
+    /// Evaluate the expression, looking up each name with `is_met`
+    ///
+    /// `&&` and `||` short-circuit, so names on their right are only looked
+    /// up when they decide the result.
+    pub fn evaluate<F>(&self, is_met: &mut F) -> Result<bool>
+    where
+        F: FnMut(&str) -> Result<bool>,
+    {
+        Ok(match self {
+            Condition::Name(name) => is_met(name)?,
+            Condition::Not(inner) => !inner.evaluate(is_met)?,
+            Condition::And(left, right) => left.evaluate(is_met)? && right.evaluate(is_met)?,
+            Condition::Or(left, right) => left.evaluate(is_met)? || right.evaluate(is_met)?,
+        })
+    }
+
+    /// Write an operand of `&&` or `!`, parenthesized if it binds looser
+    fn fmt_operand(&self, f: &mut fmt::Formatter<'_>, parenthesize_and: bool) -> fmt::Result {
+        match self {
+            Condition::Or(..) => write!(f, "({})", self),
+            Condition::And(..) if parenthesize_and => write!(f, "({})", self),
+            _ => write!(f, "{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    /// Print the expression in a form that parses back to it
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Name(name) => f.write_str(name),
+            Condition::Not(inner) => {
+                f.write_str("!")?;
+                inner.fmt_operand(f, true)
+            }
+            Condition::And(left, right) => {
+                left.fmt_operand(f, false)?;
+                f.write_str(" && ")?;
+                right.fmt_operand(f, true)
+            }
+            Condition::Or(left, right) => {
+                write!(f, "{} || ", left)?;
+                match **right {
+                    Condition::Or(..) => write!(f, "({})", right),
+                    _ => write!(f, "{}", right),
+                }
+            }
+        }
+    }
+}
+
+/// A token of a condition expression
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Name(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Name(name) => f.write_str(name),
+            Token::Not => f.write_str("!"),
+            Token::And => f.write_str("&&"),
+            Token::Or => f.write_str("||"),
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+        }
+    }
+}
+
+/// Split a condition expression into tokens
+///
+/// A name runs up to whitespace or one of `(`, `)`, `&` and `|`, so names
+/// such as `exec:git` or `kernel>=5.10` need no quoting.
+fn tokenize(text: &str) -> std::result::Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '!' => {
+                chars.next();
+                tokens.push(Token::Not);
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '&' | '|' => {
+                chars.next();
+                if chars.next_if_eq(&c).is_none() {
+                    return Err(format!("Expected '{}{}' in condition '[{}]'", c, c, text));
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            _ => {
+                let mut name = String::new();
+                while let Some(c) =
+                    chars.next_if(|&c| !c.is_whitespace() && !matches!(c, '(' | ')' | '&' | '|'))
+                {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser over condition tokens
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next_if_eq(&mut self, token: &Token) -> bool {
+        let matched = self.peek() == Some(token);
+        if matched {
+            self.pos += 1;
+        }
+        matched
+    }
+
+    /// `and ("||" and)*`
+    fn or(&mut self) -> std::result::Result<Condition, String> {
+        let mut condition = self.and()?;
+        while self.next_if_eq(&Token::Or) {
+            condition = Condition::Or(Box::new(condition), Box::new(self.and()?));
+        }
+        Ok(condition)
+    }
+
+    /// `unary ("&&" unary)*`
+    fn and(&mut self) -> std::result::Result<Condition, String> {
+        let mut condition = self.unary()?;
+        while self.next_if_eq(&Token::And) {
+            condition = Condition::And(Box::new(condition), Box::new(self.unary()?));
+        }
+        Ok(condition)
+    }
+
+    /// `"!" unary | "(" or ")" | name`
+    fn unary(&mut self) -> std::result::Result<Condition, String> {
+        let token = self.peek().cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Not) => Ok(Condition::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let condition = self.or()?;
+                if self.next_if_eq(&Token::Close) {
+                    Ok(condition)
+                } else {
+                    Err("Unclosed '('".to_string())
+                }
+            }
+            Some(Token::Name(name)) => Ok(Condition::Name(name)),
+            Some(token) => Err(format!("Unexpected '{}'", token)),
+            None => Err("Missing condition name".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(name: &str) -> Box<Condition> {
+        Box::new(Condition::Name(name.to_string()))
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let condition = Condition::parse("!a && b || c", 1).unwrap();
+        assert_eq!(
+            condition,
+            Condition::Or(
+                Box::new(Condition::And(
+                    Box::new(Condition::Not(name("a"))),
+                    name("b")
+                )),
+                name("c"),
+            )
+        );
+
+        let condition = Condition::parse("a && (b || !exec:git)", 1).unwrap();
+        assert_eq!(
+            condition,
+            Condition::And(
+                name("a"),
+                Box::new(Condition::Or(
+                    name("b"),
+                    Box::new(Condition::Not(name("exec:git")))
+                )),
+            )
+        );
+        assert_eq!(condition.names(), vec!["a", "b", "exec:git"]);
+    }
+
+    #[test]
+    fn test_display_round_trips() {
+        for text in [
+            "unix",
+            "!env:CI",
+            "a && b && c",
+            "a || b && c",
+            "(a || b) && !(c && d)",
+            "a && (b && c)",
+            "a || (b || c)",
+            "!!a",
+            "kernel>=5.10",
+        ] {
+            let condition = Condition::parse(text, 1).unwrap();
+            assert_eq!(condition.to_string(), text);
+            assert_eq!(
+                Condition::parse(&condition.to_string(), 1).unwrap(),
+                condition
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for (text, expected) in [
+            ("", "Empty condition"),
+            ("  ", "Empty condition"),
+            ("a &", "Expected '&&'"),
+            ("a | b", "Expected '||'"),
+            ("a &&", "Missing condition name"),
+            ("(a || b", "Unclosed '('"),
+            ("a)", "Unexpected ')'"),
+            ("a b", "Unexpected 'b'"),
+            ("!", "Missing condition name"),
+        ] {
+            let error = Condition::parse(text, 3).unwrap_err().to_string();
+            assert!(error.contains(expected), "{:?} gave: {}", text, error);
+            assert!(error.contains("line 3"), "{:?} gave: {}", text, error);
+        }
+    }
+
+    #[test]
+    fn test_evaluate_short_circuits() {
+        let condition = Condition::parse("yes || unknown", 1).unwrap();
+        let mut looked_up = Vec::new();
+        let met = condition
+            .evaluate(&mut |name: &str| {
+                looked_up.push(name.to_string());
+                match name {
+                    "yes" => Ok(true),
+                    _ => Err(Error::UnknownCondition {
+                        condition: name.to_string(),
+                    }),
+                }
+            })
+            .unwrap();
+        assert!(met);
+        assert_eq!(looked_up, vec!["yes"]);
+
+        let condition = Condition::parse("!(yes && no)", 1).unwrap();
+        let met = condition
+            .evaluate(&mut |name: &str| Ok(name == "yes"))
+            .unwrap();
+        assert!(met);
+    }
+}
@@ -0,0 +1,555 @@
+//! Lossless concrete syntax tree for scripts
+//!
+//! A [`SyntaxTree`] keeps the exact text of a script together with the byte
+//! spans of its lines, comments, command words and archive files. Printing a
+//! tree gives back its text byte for byte, so tools such as update mode can
+//! make surgical edits without disturbing indentation, quoting, line endings
+//! or anything else they didn't touch.
+
+use super::{parse_command_syntax, parse_file_header, Command, Comment, Script, TxtarFile};
+use crate::error::{Error, Result};
+use std::fmt;
+use std::ops::Range;
+
+/// A range of bytes in a script's text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// The offset of the first byte
+    pub start: usize,
+    /// The offset just past the last byte
+    pub end: usize,
+}
+
+impl Span {
+    /// Create a span from `start` up to, but not including, `end`
+    pub fn new(start: usize, end: usize) -> Self {
+        Span { start, end }
+    }
+
+    /// The span as a range, for slicing the text
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// The number of bytes covered
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    /// Whether the span covers no bytes
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    fn shift(self, offset: usize) -> Self {
+        Span::new(self.start + offset, self.end + offset)
+    }
+}
+
+/// A line of the script section, before the first file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptLine {
+    /// The 1-based line number
+    pub line_num: usize,
+    /// The line without its terminator
+    pub span: Span,
+    /// What the line holds
+    pub kind: LineKind,
+}
+
+/// What a line of the script section holds
+#[derive(Debug, Clone, PartialEq)]
+pub enum LineKind {
+    /// No command, such as an empty line
+    Blank,
+    /// A comment line
+    Comment(Comment),
+    /// A command line
+    Command(Box<CommandLine>),
+}
+
+/// A command line with the spans of its parts
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandLine {
+    /// The parsed command
+    pub command: Command,
+    /// The condition, including its brackets
+    pub condition: Option<Span>,
+    /// The `!` or `?` prefix
+    pub prefix: Option<Span>,
+    /// The command name
+    pub name: Span,
+    /// Each argument as written, including any quotes
+    pub args: Vec<Span>,
+    /// The `&` or `&name&` marker
+    pub background: Option<Span>,
+}
+
+impl CommandLine {
+    fn shift(mut self, offset: usize) -> Self {
+        self.condition = self.condition.map(|span| span.shift(offset));
+        self.prefix = self.prefix.map(|span| span.shift(offset));
+        self.name = self.name.shift(offset);
+        for arg in &mut self.args {
+            *arg = arg.shift(offset);
+        }
+        self.background = self.background.map(|span| span.shift(offset));
+        self
+    }
+}
+
+/// A file in the script's archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileSection {
+    /// The name in the header
+    pub name: String,
+    /// The `-- name --` line without its terminator
+    pub header: Span,
+    /// Everything from the line after the header up to the next header or
+    /// the end of the script
+    pub body: Span,
+}
+
+/// A replacement of the text in a span
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    /// The text to replace
+    pub span: Span,
+    /// The text to put in its place
+    pub text: String,
+}
+
+impl Edit {
+    /// Replace the text in `span` with `text`
+    pub fn new(span: Span, text: impl Into<String>) -> Self {
+        Edit {
+            span,
+            text: text.into(),
+        }
+    }
+}
+
+/// A script and its archive, with the spans of every part
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxTree {
+    text: String,
+    lines: Vec<ScriptLine>,
+    files: Vec<FileSection>,
+}
+
+impl SyntaxTree {
+    /// Parse a script, keeping its exact text
+    ///
+    /// # Errors
+    /// Returns ParseError if a command line is malformed
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut lines = Vec::new();
+        let mut files: Vec<FileSection> = Vec::new();
+
+        for (index, (span, next)) in line_spans(text).into_iter().enumerate() {
+            let line_num = index + 1;
+            let line = &text[span.range()];
+
+            if let Some(name) = parse_file_header(line) {
+                if let Some(previous) = files.last_mut() {
+                    previous.body.end = span.start;
+                }
+                files.push(FileSection {
+                    name,
+                    header: span,
+                    body: Span::new(next, text.len()),
+                });
+                continue;
+            }
+            if !files.is_empty() {
+                // Part of a file's body
+                continue;
+            }
+
+            let kind = if line.trim_start().starts_with('#') {
+                LineKind::Comment(Comment {
+                    text: line.trim().to_string(),
+                    line_num,
+                })
+            } else {
+                match parse_command_syntax(line, line_num)? {
+                    Some(command) => LineKind::Command(Box::new(command.shift(span.start))),
+                    None => LineKind::Blank,
+                }
+            };
+            lines.push(ScriptLine {
+                line_num,
+                span,
+                kind,
+            });
+        }
+
+        Ok(SyntaxTree {
+            text: text.to_string(),
+            lines,
+            files,
+        })
+    }
+
+    /// The script's text
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The text in a span
+    pub fn slice(&self, span: Span) -> &str {
+        &self.text[span.range()]
+    }
+
+    /// The lines of the script section, before the first file
+    pub fn lines(&self) -> &[ScriptLine] {
+        &self.lines
+    }
+
+    /// The files of the archive, in order
+    pub fn files(&self) -> &[FileSection] {
+        &self.files
+    }
+
+    /// The command on a line, if there is one
+    pub fn command_at(&self, line_num: usize) -> Option<&CommandLine> {
+        self.lines
+            .iter()
+            .find(|line| line.line_num == line_num)
+            .and_then(|line| match &line.kind {
+                LineKind::Command(command) => Some(command.as_ref()),
+                _ => None,
+            })
+    }
+
+    /// The file with a name; if several share it, the last, which is the one
+    /// left in the working directory
+    pub fn file(&self, name: &str) -> Option<&FileSection> {
+        self.files.iter().rev().find(|file| file.name == name)
+    }
+
+    /// The contents a file is created with
+    ///
+    /// These are the body's lines joined with newlines, leaving out blank
+    /// lines, as [`super::parse`] reads them.
+    pub fn file_contents(&self, file: &FileSection) -> Vec<u8> {
+        self.slice(file.body)
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+            .into_bytes()
+    }
+
+    /// The edit that replaces the body of the named file with `contents`
+    ///
+    /// The body keeps the header's line ending, and ends with one unless it
+    /// is the last thing in a script that didn't.
+    pub fn file_contents_edit(&self, name: &str, contents: &str) -> Option<Edit> {
+        let file = self.file(name)?;
+        let newline = match &self.text[file.header.end..file.body.start] {
+            "\r\n" => "\r\n",
+            _ => "\n",
+        };
+
+        let mut body = String::new();
+        if !contents.is_empty() {
+            if file.body.start == file.header.end {
+                // The header ends the script without a line ending
+                body.push_str(newline);
+            }
+            body.push_str(&contents.replace('\n', newline));
+            if self.text[..file.body.end].ends_with('\n') {
+                body.push_str(newline);
+            }
+        }
+        Some(Edit::new(file.body, body))
+    }
+
+    /// The edit that adds a file with `contents` to the end of the archive
+    pub fn append_file_edit(&self, name: &str, contents: &str) -> Edit {
+        let newline = if self.text.contains("\r\n") {
+            "\r\n"
+        } else {
+            "\n"
+        };
+
+        let mut section = String::new();
+        if !self.text.is_empty() && !self.text.ends_with('\n') {
+            section.push_str(newline);
+        }
+        section.push_str(&format!("-- {} --{}", name, newline));
+        if !contents.is_empty() {
+            section.push_str(&contents.replace('\n', newline));
+            section.push_str(newline);
+        }
+        let end = self.text.len();
+        Edit::new(Span::new(end, end), section)
+    }
+
+    /// Apply edits to the text and parse it again
+    ///
+    /// The edits must not overlap. Their spans refer to the text before any
+    /// of them is applied, and all spans taken from the tree are stale
+    /// afterwards.
+    ///
+    /// # Errors
+    /// Returns an error, leaving the tree unchanged, if the edits overlap or
+    /// fall outside the text, or if the edited script doesn't parse
+    pub fn apply(&mut self, mut edits: Vec<Edit>) -> Result<()> {
+        edits.sort_by_key(|edit| (edit.span.start, edit.span.end));
+
+        let mut text = String::with_capacity(self.text.len());
+        let mut copied = 0;
+        for edit in &edits {
+            let Span { start, end } = edit.span;
+            if start < copied
+                || start > end
+                || !self.text.is_char_boundary(start)
+                || !self.text.is_char_boundary(end)
+            {
+                return Err(Error::Generic(format!(
+                    "Invalid edit of bytes {}..{} of the script",
+                    start, end
+                )));
+            }
+            text.push_str(&self.text[copied..start]);
+            text.push_str(&edit.text);
+            copied = end;
+        }
+        text.push_str(&self.text[copied..]);
+
+        *self = SyntaxTree::parse(&text)?;
+        Ok(())
+    }
+
+    /// Lower the tree into the commands, comments and files a run needs
+    pub fn to_script(&self) -> Script {
+        let mut commands = Vec::new();
+        let mut comments = Vec::new();
+        for line in &self.lines {
+            match &line.kind {
+                LineKind::Blank => {}
+                LineKind::Comment(comment) => comments.push(comment.clone()),
+                LineKind::Command(command) => commands.push(command.command.clone()),
+            }
+        }
+
+        let files = self
+            .files
+            .iter()
+            .map(|file| TxtarFile {
+                name: file.name.clone(),
+                contents: self.file_contents(file),
+            })
+            .collect();
+
+        Script {
+            commands,
+            comments,
+            files,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Split text into lines as `str::lines` does, giving the span of each line
+/// without its terminator and the offset where the next line starts
+fn line_spans(text: &str) -> Vec<(Span, usize)> {
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let (end, next) = match text[start..].find('\n') {
+            Some(newline) => {
+                let newline = start + newline;
+                let end = if text[..newline].ends_with('\r') {
+                    newline - 1
+                } else {
+                    newline
+                };
+                (end, newline + 1)
+            }
+            None => (text.len(), text.len()),
+        };
+        spans.push((Span::new(start, end), next));
+        start = next;
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCRIPT: &str = "# setup\n\
+        [unix] ! exec\tcat  'a file' &cat&\n\
+        \n\
+        \tstdout -count=2 \"x y\"\n\
+        -- a file --\n\
+        first\n\
+        \n\
+        second\n\
+        -- empty --\n";
+
+    fn command_words(tree: &SyntaxTree, line_num: usize) -> Vec<&str> {
+        let command = tree.command_at(line_num).unwrap();
+        std::iter::once(command.name)
+            .chain(command.args.iter().copied())
+            .map(|span| tree.slice(span))
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for text in [
+            SCRIPT,
+            "",
+            "\n\n",
+            "exec echo hi",
+            "exec echo hi\r\n-- f --\r\nbody\r\n",
+            "-- f --",
+            "  # indented\n\t\texec  echo   'x'  \n-- f --\nno newline",
+        ] {
+            let tree = SyntaxTree::parse(text).unwrap();
+            assert_eq!(tree.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn test_spans() {
+        let tree = SyntaxTree::parse(SCRIPT).unwrap();
+
+        assert_eq!(tree.lines().len(), 4);
+        assert!(matches!(tree.lines()[0].kind, LineKind::Comment(_)));
+        assert!(matches!(tree.lines()[2].kind, LineKind::Blank));
+
+        let command = tree.command_at(2).unwrap();
+        assert_eq!(tree.slice(command.condition.unwrap()), "[unix]");
+        assert_eq!(tree.slice(command.prefix.unwrap()), "!");
+        assert_eq!(tree.slice(command.background.unwrap()), "&cat&");
+        assert_eq!(command_words(&tree, 2), vec!["exec", "cat", "'a file'"]);
+        assert_eq!(command.command.args, vec!["cat", "a file"]);
+
+        assert_eq!(
+            command_words(&tree, 4),
+            vec!["stdout", "-count=2", "\"x y\""]
+        );
+        assert_eq!(tree.command_at(3), None);
+
+        let conditions = SyntaxTree::parse("  [unix]  [!net] exists a\n").unwrap();
+        let command = conditions.command_at(1).unwrap();
+        assert_eq!(
+            conditions.slice(command.condition.unwrap()),
+            "[unix]  [!net]"
+        );
+        assert_eq!(conditions.slice(command.name), "exists");
+
+        let files = tree.files();
+        assert_eq!(files.len(), 2);
+        assert_eq!(tree.slice(files[0].header), "-- a file --");
+        assert_eq!(tree.slice(files[0].body), "first\n\nsecond\n");
+        assert_eq!(tree.file_contents(&files[0]), b"first\nsecond");
+        assert_eq!(tree.slice(files[1].body), "");
+    }
+
+    #[test]
+    fn test_lowering_matches_parse() {
+        let tree = SyntaxTree::parse(SCRIPT).unwrap();
+        let script = tree.to_script();
+        assert_eq!(script.commands.len(), 2);
+        assert_eq!(script.comments.len(), 1);
+        assert_eq!(script.files[0].contents, b"first\nsecond");
+        assert_eq!(script.files[1].contents, b"");
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let mut tree = SyntaxTree::parse(SCRIPT).unwrap();
+        let pattern = tree.command_at(4).unwrap().args[1];
+        let name = tree.command_at(2).unwrap().args[0];
+        tree.apply(vec![Edit::new(pattern, "'z'"), Edit::new(name, "tac")])
+            .unwrap();
+
+        assert_eq!(
+            tree.text(),
+            SCRIPT
+                .replace("exec\tcat", "exec\ttac")
+                .replace("\"x y\"", "'z'")
+        );
+        assert_eq!(
+            tree.command_at(4).unwrap().command.args,
+            vec!["-count=2", "z"]
+        );
+
+        let span = tree.command_at(2).unwrap().name;
+        assert!(tree
+            .apply(vec![Edit::new(span, "a"), Edit::new(span, "b")])
+            .is_err());
+        let condition = tree.command_at(2).unwrap().condition.unwrap();
+        assert!(tree.apply(vec![Edit::new(condition, "[broken")]).is_err());
+        assert_eq!(tree.command_at(2).unwrap().name, span);
+    }
+
+    #[test]
+    fn test_file_contents_edit() {
+        let edit_file = |text: &str, name: &str, contents: &str| {
+            let mut tree = SyntaxTree::parse(text).unwrap();
+            let edit = tree.file_contents_edit(name, contents).unwrap();
+            tree.apply(vec![edit]).unwrap();
+            tree.to_string()
+        };
+
+        assert_eq!(
+            edit_file("-- a --\nold\n\n-- b --\nkept\n", "a", "new\nlines"),
+            "-- a --\nnew\nlines\n-- b --\nkept\n"
+        );
+        assert_eq!(edit_file("-- a --\nold", "a", "new"), "-- a --\nnew");
+        assert_eq!(edit_file("-- a --\nold\n", "a", "new"), "-- a --\nnew\n");
+        assert_eq!(edit_file("-- a --", "a", "new"), "-- a --\nnew");
+        assert_eq!(
+            edit_file("-- a --\n-- b --\n", "a", "new"),
+            "-- a --\nnew\n-- b --\n"
+        );
+        assert_eq!(
+            edit_file("-- a --\r\nold\r\n", "a", "x\ny"),
+            "-- a --\r\nx\r\ny\r\n"
+        );
+        assert_eq!(
+            edit_file("-- a --\nold\n-- b --\n", "a", ""),
+            "-- a --\n-- b --\n"
+        );
+
+        let tree = SyntaxTree::parse("-- a --\n").unwrap();
+        assert_eq!(tree.file_contents_edit("missing", "x"), None);
+    }
+
+    #[test]
+    fn test_append_file_edit() {
+        let append_file = |text: &str| {
+            let mut tree = SyntaxTree::parse(text).unwrap();
+            let edit = tree.append_file_edit("new", "one\ntwo");
+            tree.apply(vec![edit]).unwrap();
+            assert_eq!(
+                tree.file("new").map(|f| tree.file_contents(f)),
+                Some(b"one\ntwo".to_vec())
+            );
+            tree.to_string()
+        };
+
+        assert_eq!(append_file(""), "-- new --\none\ntwo\n");
+        assert_eq!(append_file("exec true"), "exec true\n-- new --\none\ntwo\n");
+        assert_eq!(
+            append_file("exec true\n-- a --\nx\n"),
+            "exec true\n-- a --\nx\n-- new --\none\ntwo\n"
+        );
+        assert_eq!(
+            append_file("exec true\r\n"),
+            "exec true\r\n-- new --\r\none\r\ntwo\r\n"
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Structured results of a test run
+
+use crate::error::{Error, Result};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// How a single script finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// Every command succeeded
+    Passed,
+    /// A command failed, or the script could not be run
+    Failed,
+    /// The script ended early with `skip`
+    Skipped {
+        /// The message given to `skip`
+        reason: String,
+    },
+    /// The script ended early with `stop`, which counts as passing
+    Stopped {
+        /// The message given to `stop`
+        reason: String,
+    },
+}
+
+/// The result of running a single script
+#[derive(Debug)]
+pub struct ScriptReport {
+    /// Path of the script file
+    pub path: PathBuf,
+    /// How the script finished
+    pub outcome: Outcome,
+    /// How long the script took to run
+    pub duration: Duration,
+    /// The script line that failed, if known
+    pub failing_line: Option<usize>,
+    /// The error that failed the script
+    pub error: Option<Error>,
+}
+
+impl ScriptReport {
+    /// Whether the script counts as a failure
+    pub fn is_failure(&self) -> bool {
+        self.outcome == Outcome::Failed
+    }
+}
+
+/// Per-script results of a test run, in execution order
+#[derive(Debug, Default)]
+pub struct RunReport {
+    /// One entry per script that was run
+    pub scripts: Vec<ScriptReport>,
+}
+
+impl RunReport {
+    /// Number of scripts that passed, including those that stopped early
+    pub fn passed(&self) -> usize {
+        self.scripts
+            .iter()
+            .filter(|s| matches!(s.outcome, Outcome::Passed | Outcome::Stopped { .. }))
+            .count()
+    }
+
+    /// Number of scripts that failed
+    pub fn failed(&self) -> usize {
+        self.scripts.iter().filter(|s| s.is_failure()).count()
+    }
+
+    /// Number of scripts that were skipped
+    pub fn skipped(&self) -> usize {
+        self.scripts
+            .iter()
+            .filter(|s| matches!(s.outcome, Outcome::Skipped { .. }))
+            .count()
+    }
+
+    /// Whether no script failed
+    pub fn is_success(&self) -> bool {
+        self.failed() == 0
+    }
+
+    /// Total time spent running scripts
+    pub fn total_duration(&self) -> Duration {
+        self.scripts.iter().map(|s| s.duration).sum()
+    }
+
+    /// Convert the report into the result returned by `Builder::execute`
+    ///
+    /// Returns [`Error::ScriptsFailed`] listing every failed script if any failed.
+    pub fn into_result(self) -> Result<()> {
+        if self.is_success() {
+            return Ok(());
+        }
+
+        let passed = self.passed();
+        let skipped = self.skipped();
+        let failures = self
+            .scripts
+            .into_iter()
+            .filter_map(|script| {
+                let path = script.path.display().to_string();
+                script.error.map(|error| (path, error))
+            })
+            .collect();
+
+        Err(Error::ScriptsFailed {
+            failures,
+            passed,
+            skipped,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(name: &str, outcome: Outcome, error: Option<Error>) -> ScriptReport {
+        ScriptReport {
+            path: PathBuf::from(name),
+            failing_line: error.as_ref().and_then(|e| e.line_num()),
+            outcome,
+            duration: Duration::from_millis(10),
+            error,
+        }
+    }
+
+    #[test]
+    fn test_counts() {
+        let report = RunReport {
+            scripts: vec![
+                script("a.txt", Outcome::Passed, None),
+                script(
+                    "b.txt",
+                    Outcome::Stopped {
+                        reason: "done".into(),
+                    },
+                    None,
+                ),
+                script(
+                    "c.txt",
+                    Outcome::Skipped {
+                        reason: "later".into(),
+                    },
+                    None,
+                ),
+                script("d.txt", Outcome::Failed, Some(Error::Generic("bad".into()))),
+            ],
+        };
+
+        assert_eq!(report.passed(), 2);
+        assert_eq!(report.skipped(), 1);
+        assert_eq!(report.failed(), 1);
+        assert!(!report.is_success());
+        assert_eq!(report.total_duration(), Duration::from_millis(40));
+
+        let err = report.into_result().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Test 'd.txt' failed: bad\n\n2 passed, 1 failed, 1 skipped"
+        );
+    }
+
+    #[test]
+    fn test_empty_report_succeeds() {
+        assert!(RunReport::default().into_result().is_ok());
+    }
+}
@@ -1,5 +1,6 @@
 //! Built-in command implementations
 
+use crate::diff::unified_diff;
 use crate::error::{Error, Result};
 use crate::run::environment::TestEnvironment;
 use regex::Regex;
@@ -114,10 +115,10 @@ impl TestEnvironment {
 
     /// Compare files with environment variable substitution in the second file
     pub fn compare_files_with_env(&self, file1: &str, file2: &str) -> Result<()> {
-        let path1 = self.work_dir.join(file1);
         let path2 = self.work_dir.join(file2);
 
-        let contents1 = fs::read_to_string(&path1).map_err(|e| {
+        let contents1 = self.compared_contents("cmpenv", file1)?;
+        let contents1 = String::from_utf8(contents1).map_err(|e| {
             Error::command_error("cmpenv", format!("Cannot read '{}': {}", file1, e))
         })?;
 
@@ -126,13 +127,18 @@ impl TestEnvironment {
         })?;
 
         // Substitute environment variables in file2
-        let contents2 = self.substitute_env_vars(&contents2_raw);
+        let contents2 = self.substitute_env_vars(&contents2_raw)?;
 
         if contents1.trim() != contents2.trim() {
             return Err(Error::FileCompare {
                 message: format!(
-                    "Files differ after environment substitution:\n'{}' contains:\n{}\n\n'{}' (after substitution) contains:\n{}",
-                    file1, contents1, file2, contents2
+                    "Files differ after environment substitution:\n{}",
+                    unified_diff(
+                        file1,
+                        &format!("{} (after substitution)", file2),
+                        contents1.trim(),
+                        contents2.trim()
+                    )
                 ),
             });
         }
@@ -146,7 +152,7 @@ impl TestEnvironment {
 
         // Parse the octal mode (e.g., "444", "755")
         let mode_int = u32::from_str_radix(mode, 8)
-            .map_err(|_| Error::command_error("chmod", format!("Invalid mode: {}", mode)))?;
+            .map_err(|_| Error::usage_error("chmod", format!("Invalid mode: {}", mode)))?;
 
         #[cfg(unix)]
         {
@@ -226,7 +232,7 @@ impl TestEnvironment {
             format!("(?u){}", pattern)
         };
         let regex = Regex::new(&pattern_with_flags)
-            .map_err(|e| Error::command_error("grep", format!("Invalid regex: {}", e)))?;
+            .map_err(|e| Error::usage_error("grep", format!("Invalid regex: {}", e)))?;
 
         let mut _found_matches = false;
         let mut output = String::new();
@@ -261,6 +267,7 @@ impl TestEnvironment {
             stdout: output.trim_end().as_bytes().to_vec(),
             stderr: Vec::new(),
         });
+        self.last_output_running = false;
 
         Ok(())
     }
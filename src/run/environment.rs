@@ -1,14 +1,68 @@
 //! Test execution environment management
 
+use crate::diff::unified_diff;
 use crate::error::{Error, Result};
-use crate::parser::TxtarFile;
+use crate::parser::{Expectation, TxtarFile};
+use crate::run::process::{self, OutputCapture};
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::process::{Child, Command as StdCommand, Output, Stdio};
+use std::time::{Duration, Instant};
 use tempfile::TempDir;
 
+/// Lines of each output stream kept from the start and end in failure reports
+const REPORT_OUTPUT_LINES: usize = 10;
+
+/// Characters of a single output line kept in failure reports
+const REPORT_LINE_CHARS: usize = 200;
+
+/// How often `waitoutput` checks a background process's output
+const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How long `waitoutput` waits when neither it nor the script sets a timeout
+const DEFAULT_OUTPUT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The process most recently started by `exec`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastExec {
+    /// The program and its arguments, after expansion
+    pub argv: Vec<String>,
+    /// The directory the process ran in
+    pub cwd: PathBuf,
+}
+
+/// A process started with `exec ... &`
+#[derive(Debug)]
+pub struct BackgroundProcess {
+    /// The running process
+    pub child: Child,
+    /// The command line that started it, for messages
+    pub command: String,
+    /// How the process must exit, checked when it is waited for
+    pub expectation: Expectation,
+    /// When the process was started relative to the script's others
+    pub sequence: usize,
+    /// The process's output so far, drained as it runs
+    stdout: OutputCapture,
+    stderr: OutputCapture,
+}
+
+impl BackgroundProcess {
+    /// The output the process has produced so far
+    ///
+    /// The process hasn't necessarily exited, so the status is only a
+    /// placeholder success.
+    pub fn output_so_far(&self) -> Output {
+        Output {
+            status: process::running_status(),
+            stdout: self.stdout.snapshot(),
+            stderr: self.stderr.snapshot(),
+        }
+    }
+}
+
 /// Test execution environment for a single script run
 pub struct TestEnvironment {
     /// The root temporary directory for the test run
@@ -21,14 +75,29 @@ pub struct TestEnvironment {
     pub current_dir: PathBuf,
     /// Output from the last executed command
     pub last_output: Option<Output>,
+    /// Whether the last output was taken from a background process that is
+    /// still running, so its status says nothing about how it exits
+    pub last_output_running: bool,
+    /// The last process started, in the foreground or background
+    pub last_exec: Option<LastExec>,
     /// Background processes indexed by name
-    pub background_processes: HashMap<String, Child>,
+    pub background_processes: HashMap<String, BackgroundProcess>,
+    /// Number of background processes started so far
+    background_started: usize,
     /// Standard input content for the next exec command
     pub next_stdin: Option<Vec<u8>>,
     /// Whether the test should be skipped
     pub should_skip: bool,
     /// Whether the test should stop early (but pass)
     pub should_stop: bool,
+    /// Message given to `skip` or `stop`, if any
+    pub exit_reason: Option<String>,
+    /// Whether commands run without inheriting the host environment
+    pub hermetic: bool,
+    /// Maximum time a single command may run before it is killed
+    pub command_timeout: Option<Duration>,
+    /// When the script must finish, with the timeout it was derived from
+    pub script_deadline: Option<(Instant, Duration)>,
 }
 
 impl TestEnvironment {
@@ -70,16 +139,44 @@ impl TestEnvironment {
         Ok(TestEnvironment {
             work_dir: work_dir.clone(),
             _temp_dir: temp_dir,
-            env_vars: HashMap::new(),
+            env_vars: Self::predefined_env_vars(),
             current_dir: work_dir,
             last_output: None,
+            last_output_running: false,
+            last_exec: None,
             background_processes: HashMap::new(),
+            background_started: 0,
             next_stdin: None,
             should_skip: false,
             should_stop: false,
+            exit_reason: None,
+            hermetic: false,
+            command_timeout: None,
+            script_deadline: None,
         })
     }
 
+    /// Variables that Go testscript predefines for every script
+    ///
+    /// - `/` - the path separator, used as `${/}`
+    /// - `:` - the path list separator, used as `${:}`
+    /// - `exe` - `.exe` on Windows, empty elsewhere
+    /// - `devnull` - the null device
+    fn predefined_env_vars() -> HashMap<String, String> {
+        let devnull = if cfg!(windows) { "NUL" } else { "/dev/null" };
+        let exe = if cfg!(windows) { ".exe" } else { "" };
+
+        [
+            ("/", std::path::MAIN_SEPARATOR.to_string()),
+            (":", if cfg!(windows) { ";" } else { ":" }.to_string()),
+            ("exe", exe.to_string()),
+            ("devnull", devnull.to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+    }
+
     /// Set up files from the parsed script in the work directory
     pub fn setup_files(&mut self, files: &[TxtarFile]) -> Result<()> {
         for file in files {
@@ -96,58 +193,460 @@ impl TestEnvironment {
         Ok(())
     }
 
-    /// Execute a command in the current test environment
-    pub fn execute_command(&mut self, cmd: &str, args: &[String]) -> Result<Output> {
+    /// Switch to a hermetic environment seeded with a well-defined baseline
+    ///
+    /// Commands stop inheriting the host environment. The baseline contains
+    /// `WORK`, `HOME=/no-home`, `TMPDIR=$WORK/.tmp` and the host `PATH`, plus
+    /// any host variables named in `passthrough` that are set.
+    pub fn make_hermetic(&mut self, passthrough: &[String]) -> Result<()> {
+        self.hermetic = true;
+
+        let tmp_dir = self.work_dir.join(".tmp");
+        fs::create_dir_all(&tmp_dir)?;
+        let tmp_dir = tmp_dir.to_string_lossy().to_string();
+        let work_dir = self.work_dir.to_string_lossy().to_string();
+
+        self.set_env_var("WORK", &work_dir);
+        self.set_env_var("HOME", "/no-home");
+        self.set_env_var("TMPDIR", &tmp_dir);
+        self.set_env_var("PATH", &std::env::var("PATH").unwrap_or_default());
+
+        #[cfg(windows)]
+        {
+            self.set_env_var("USERPROFILE", "/no-home");
+            self.set_env_var("TMP", &tmp_dir);
+            self.set_env_var("TEMP", &tmp_dir);
+            // Many Windows programs fail to start without these
+            for key in ["SYSTEMROOT", "COMSPEC", "PATHEXT"] {
+                if let Ok(value) = std::env::var(key) {
+                    self.set_env_var(key, &value);
+                }
+            }
+        }
+
+        for key in passthrough {
+            if let Ok(value) = std::env::var(key) {
+                self.set_env_var(key, &value);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Build a process command that runs with this environment's variables
+    fn new_process(&mut self, cmd: &str, args: &[String]) -> StdCommand {
+        self.last_exec = Some(LastExec {
+            argv: std::iter::once(cmd.to_string())
+                .chain(args.iter().cloned())
+                .collect(),
+            cwd: self.current_dir.clone(),
+        });
+
         let mut command = StdCommand::new(cmd);
+        command.args(args).current_dir(&self.current_dir);
+        if self.hermetic {
+            command.env_clear();
+        }
+        command.envs(&self.env_vars);
+        if self.command_timeout.is_some() || self.script_deadline.is_some() {
+            // Let a timeout kill the process along with anything it started
+            process::set_new_process_group(&mut command);
+        }
         command
-            .args(args)
-            .current_dir(&self.current_dir)
-            .envs(&self.env_vars);
-
-        let output = if let Some(stdin_content) = self.next_stdin.take() {
-            command.stdin(Stdio::piped());
-            let mut child = command.spawn()?;
-            if let Some(mut stdin) = child.stdin.take() {
-                use std::io::Write;
-                stdin.write_all(&stdin_content)?;
+    }
+
+    /// Describe the last command for a failure report
+    ///
+    /// This covers the command line and directory of the last `exec`, so the
+    /// failure can be reproduced by hand, and the exit status and output of
+    /// the last command, with long output shortened.
+    pub fn describe_last_command(&self) -> Option<String> {
+        if self.last_exec.is_none() && self.last_output.is_none() {
+            return None;
+        }
+
+        let mut description = String::new();
+        if let Some(exec) = &self.last_exec {
+            let argv: Vec<String> = exec.argv.iter().map(|arg| shell_quote(arg)).collect();
+            description.push_str(&format!("Last exec: {}\n", argv.join(" ")));
+            description.push_str(&format!("Working directory: {}\n", exec.cwd.display()));
+        }
+        if let Some(output) = &self.last_output {
+            let status = match output.status.code() {
+                _ if self.last_output_running => "still running".to_string(),
+                Some(code) => code.to_string(),
+                None => process::describe_exit(&output.status),
+            };
+            description.push_str(&format!("Exit status: {}\n", status));
+            for (name, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+                if !bytes.is_empty() {
+                    let text = String::from_utf8_lossy(bytes);
+                    description.push_str(&format!("[{}]\n{}\n", name, truncate_output(&text)));
+                }
             }
-            child.wait_with_output()?
+        }
+        Some(description.trim_end().to_string())
+    }
+
+    /// Set how long the rest of the script may run, or `None` for no limit
+    pub fn set_script_timeout(&mut self, timeout: Option<Duration>) {
+        self.script_deadline = timeout.map(|timeout| (Instant::now() + timeout, timeout));
+    }
+
+    /// The deadline for a command started now, with the timeout that sets it
+    ///
+    /// This is the earlier of the command timeout and the script deadline.
+    pub fn command_deadline(&self) -> Option<(Instant, Duration)> {
+        let command_deadline = self
+            .command_timeout
+            .map(|timeout| (Instant::now() + timeout, timeout));
+
+        match (command_deadline, self.script_deadline) {
+            (Some(command), Some(script)) => Some(if command.0 <= script.0 {
+                command
+            } else {
+                script
+            }),
+            (command, script) => command.or(script),
+        }
+    }
+
+    /// Execute a command in the current test environment
+    ///
+    /// If a command or script timeout is set and expires, the process is
+    /// killed and an [`Error::Timeout`] with its partial output is returned.
+    pub fn execute_command(&mut self, cmd: &str, args: &[String]) -> Result<Output> {
+        let mut command = self.new_process(cmd, args);
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+
+        let stdin_content = self.next_stdin.take();
+        command.stdin(if stdin_content.is_some() {
+            Stdio::piped()
         } else {
-            command.output()?
-        };
+            Stdio::null()
+        });
 
-        self.last_output = Some(output.clone());
+        let mut child = command.spawn()?;
+        let stdout = OutputCapture::spawn(child.stdout.take().expect("stdout is piped"));
+        let stderr = OutputCapture::spawn(child.stderr.take().expect("stderr is piped"));
+
+        if let (Some(content), Some(mut stdin)) = (stdin_content, child.stdin.take()) {
+            // Write from a separate thread so a process that doesn't read all
+            // of its input can't block us past the deadline
+            std::thread::spawn(move || {
+                use std::io::Write;
+                let _ = stdin.write_all(&content);
+            });
+        }
+
+        let output = process::wait_with_deadline(
+            child,
+            stdout,
+            stderr,
+            &describe_command(cmd, args),
+            self.command_deadline(),
+        )?;
+
+        self.record_output(&output);
         Ok(output)
     }
 
+    /// Make `output` the last output and its exit code `${?}`
+    fn record_output(&mut self, output: &Output) {
+        self.set_env_var("?", &process::exit_code(&output.status).to_string());
+        self.last_output = Some(output.clone());
+        self.last_output_running = false;
+    }
+
+    /// Make the output a background process has produced so far the last
+    /// output, with its exit status if it has already exited
+    ///
+    /// `${?}` is left alone until the process is waited for.
+    fn record_background_output(&mut self, name: &str) -> Result<()> {
+        let process = self
+            .background_processes
+            .get_mut(name)
+            .expect("process is running");
+        let status = process.child.try_wait()?;
+        let mut output = process.output_so_far();
+        if let Some(status) = status {
+            output.status = status;
+        }
+        self.last_output = Some(output);
+        self.last_output_running = status.is_none();
+        Ok(())
+    }
+
     /// Execute a command in the background
+    ///
+    /// `expectation` says how the process must exit; it is checked by
+    /// [`TestEnvironment::wait_for_background`]. Starting a process clears the
+    /// last output, and it is an error to reuse the name of a process that
+    /// hasn't been waited for.
     pub fn execute_background_command(
         &mut self,
         name: &str,
         cmd: &str,
         args: &[String],
+        expectation: Expectation,
     ) -> Result<()> {
-        let mut command = StdCommand::new(cmd);
-        command
-            .args(args)
-            .current_dir(&self.current_dir)
-            .envs(&self.env_vars)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        let child = command.spawn()?;
-        self.background_processes.insert(name.to_string(), child);
+        if self.background_processes.contains_key(name) {
+            return Err(Error::usage_error(
+                "exec",
+                format!("A background process named '{}' is already running", name),
+            ));
+        }
+
+        let mut command = self.new_process(cmd, args);
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+        // Let the end of the script kill the process along with anything it
+        // started
+        process::set_new_process_group(&mut command);
+
+        let mut child = command.spawn()?;
+        // Drain the pipes right away so a chatty process never blocks on them
+        let stdout = OutputCapture::spawn(child.stdout.take().expect("stdout is piped"));
+        let stderr = OutputCapture::spawn(child.stderr.take().expect("stderr is piped"));
+        self.last_output = None;
+        self.background_started += 1;
+        self.background_processes.insert(
+            name.to_string(),
+            BackgroundProcess {
+                child,
+                command: describe_command(cmd, args),
+                expectation,
+                sequence: self.background_started,
+                stdout,
+                stderr,
+            },
+        );
         Ok(())
     }
 
+    /// A name for a new background process that no running process has
+    ///
+    /// This is `base` itself if it is free, or `base-2`, `base-3`, ...
+    pub fn unique_background_name(&self, base: &str) -> String {
+        if !self.background_processes.contains_key(base) {
+            return base.to_string();
+        }
+        (2..)
+            .map(|n| format!("{}-{}", base, n))
+            .find(|name| !self.background_processes.contains_key(name))
+            .expect("some suffix is free")
+    }
+
+    /// Names of the running background processes, in the order they started
+    pub fn background_names(&self) -> Vec<String> {
+        let mut processes: Vec<(&String, &BackgroundProcess)> =
+            self.background_processes.iter().collect();
+        processes.sort_by_key(|(_, process)| process.sequence);
+        processes
+            .into_iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Wait for every background process, in the order they started
+    ///
+    /// The last output becomes their outputs concatenated in that order, with
+    /// the status of the first process that failed, or else of the last one.
+    /// Every process is waited for even if some don't exit as expected; the
+    /// first such error is returned. With no background processes, the
+    /// result is `None` and the last output is left alone.
+    pub fn wait_for_all_background(&mut self) -> Result<Option<Output>> {
+        let mut combined: Option<Output> = None;
+        let mut first_error = None;
+
+        for name in self.background_names() {
+            self.last_output = None;
+            let output = match self.wait_for_background(&name) {
+                Ok(output) => output,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                    match self.last_output.take() {
+                        Some(output) => output,
+                        None => continue,
+                    }
+                }
+            };
+            combined = Some(match combined {
+                None => output,
+                Some(mut all) => {
+                    all.stdout.extend_from_slice(&output.stdout);
+                    all.stderr.extend_from_slice(&output.stderr);
+                    if all.status.success() {
+                        all.status = output.status;
+                    }
+                    all
+                }
+            });
+        }
+
+        if let Some(output) = &combined {
+            self.record_output(output);
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(combined),
+        }
+    }
+
+    /// Make the output a running background process has produced so far the
+    /// last output, for `stdout` and `stderr` to check
+    pub fn background_output(&mut self, name: &str) -> Result<()> {
+        if !self.background_processes.contains_key(name) {
+            return Err(Error::usage_error(
+                "bgoutput",
+                format!("No background process named '{}'", name),
+            ));
+        }
+        self.record_background_output(name)
+    }
+
+    /// Wait until a background process's stdout or stderr matches a pattern
+    ///
+    /// The pattern is a regular expression matched line by line against the
+    /// complete lines of output so far, so a line still being written isn't
+    /// matched until its newline arrives or the process exits. Each named
+    /// group that matches sets the environment variable of the same name. The wait fails if the process exits first,
+    /// or once `timeout` passes, defaulting to the command and script
+    /// timeouts, or else to ten seconds. Either way the output so far becomes
+    /// the last output.
+    pub fn wait_for_output(
+        &mut self,
+        name: &str,
+        stream: &str,
+        pattern: &str,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let regex = Regex::new(&format!("(?m){}", pattern))
+            .map_err(|e| Error::usage_error("waitoutput", format!("Invalid regex: {}", e)))?;
+        let (deadline, timeout) = match (timeout, self.command_deadline()) {
+            (None, Some(deadline)) => deadline,
+            (timeout, _) => {
+                let timeout = timeout.unwrap_or(DEFAULT_OUTPUT_TIMEOUT);
+                (Instant::now() + timeout, timeout)
+            }
+        };
+        let process = self.background_processes.get_mut(name).ok_or_else(|| {
+            Error::usage_error(
+                "waitoutput",
+                format!("No background process named '{}'", name),
+            )
+        })?;
+        let capture = match stream {
+            "stdout" => &process.stdout,
+            "stderr" => &process.stderr,
+            _ => {
+                return Err(Error::usage_error(
+                    "waitoutput",
+                    format!("Expected stdout or stderr, got '{}'", stream),
+                ))
+            }
+        };
+
+        let failure = loop {
+            let exit = process.child.try_wait()?;
+            if exit.is_some() {
+                // Let the reader catch up with the last of the output
+                capture.wait_for_close(process::KILLED_READER_GRACE);
+            }
+
+            let mut output = String::from_utf8_lossy(&capture.snapshot()).into_owned();
+            if exit.is_none() {
+                // The last line may still be partly written
+                output.truncate(output.rfind('\n').map_or(0, |i| i + 1));
+            }
+            if let Some(captures) = regex.captures(&output) {
+                for group in regex.capture_names().flatten() {
+                    if let Some(value) = captures.name(group) {
+                        self.env_vars
+                            .insert(group.to_string(), value.as_str().to_string());
+                    }
+                }
+                break None;
+            }
+
+            if let Some(status) = exit {
+                break Some(format!(
+                    "Background process '{}' exited with {} before its {} matched '{}'",
+                    name,
+                    process::describe_exit(&status),
+                    stream,
+                    pattern
+                ));
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break Some(format!(
+                    "The {} of background process '{}' did not match '{}' within {:?}",
+                    stream, name, pattern, timeout
+                ));
+            }
+            std::thread::sleep(OUTPUT_POLL_INTERVAL.min(deadline - now));
+        };
+
+        self.record_background_output(name)?;
+        match failure {
+            Some(message) => Err(Error::command_error("waitoutput", message)),
+            None => Ok(()),
+        }
+    }
+
+    /// Kill any background processes still running, along with everything
+    /// they started
+    ///
+    /// This runs at the end of a script. How the processes exit isn't
+    /// checked, since it depends on how far they got before being killed; a
+    /// process whose result matters has to be waited for before the script
+    /// ends.
+    pub fn stop_background_processes(&mut self) {
+        for name in self.background_names() {
+            if let Some(mut process) = self.background_processes.remove(&name) {
+                process::kill_process_group(&mut process.child);
+                let _ = process.child.wait();
+                // A descendant that escaped the kill may still hold the pipes
+                process.stdout.finish_within(process::KILLED_READER_GRACE);
+                process.stderr.finish_within(process::KILLED_READER_GRACE);
+            }
+        }
+    }
+
     /// Wait for a background process to complete
+    ///
+    /// The wait is bounded by the command and script timeouts, if set. The
+    /// output is recorded even if the process didn't exit as expected, in
+    /// which case an error is returned.
     pub fn wait_for_background(&mut self, name: &str) -> Result<Output> {
-        if let Some(child) = self.background_processes.remove(name) {
-            let output = child.wait_with_output()?;
-            self.last_output = Some(output.clone());
-            Ok(output)
+        if let Some(process) = self.background_processes.remove(name) {
+            let output = process::wait_with_deadline(
+                process.child,
+                process.stdout,
+                process.stderr,
+                &format!("wait {}", name),
+                self.command_deadline(),
+            )?;
+            self.record_output(&output);
+
+            let failed = !output.status.success();
+            let message = match process.expectation {
+                Expectation::Success if failed => format!(
+                    "Background process '{}' ({}) failed with {}: {}",
+                    name,
+                    process.command,
+                    process::describe_exit(&output.status),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Expectation::Failure if !failed => format!(
+                    "Background process '{}' ({}) was expected to fail but succeeded",
+                    name, process.command
+                ),
+                _ => return Ok(output),
+            };
+            Err(Error::command_error("wait", message))
         } else {
-            Err(Error::command_error(
+            Err(Error::usage_error(
                 "wait",
                 format!("No background process named '{}'", name),
             ))
@@ -181,13 +680,40 @@ impl TestEnvironment {
         Ok(())
     }
 
+    /// Read the first operand of `cmp` or `cmpenv`: a file, or the last
+    /// command's `stdout` or `stderr`
+    ///
+    /// Files in the archive don't keep their final newline, so one is dropped
+    /// from the output to match them.
+    pub fn compared_contents(&self, command: &str, name: &str) -> Result<Vec<u8>> {
+        let output = match (name, &self.last_output) {
+            ("stdout", Some(output)) => &output.stdout,
+            ("stderr", Some(output)) => &output.stderr,
+            ("stdout" | "stderr", None) => {
+                return Err(Error::command_error(
+                    command,
+                    format!("No {} available", name),
+                ))
+            }
+            _ => {
+                let path = self.work_dir.join(name);
+                return fs::read(&path).map_err(|e| {
+                    Error::command_error(command, format!("Cannot read '{}': {}", name, e))
+                });
+            }
+        };
+        let contents = output.strip_suffix(b"\n").unwrap_or(output);
+        Ok(contents.to_vec())
+    }
+
     /// Compare two files for equality
+    ///
+    /// The first may also be `stdout` or `stderr`, as read by
+    /// [`TestEnvironment::compared_contents`].
     pub fn compare_files(&self, file1: &str, file2: &str) -> Result<()> {
-        let path1 = self.work_dir.join(file1);
         let path2 = self.work_dir.join(file2);
 
-        let contents1 = fs::read(&path1)
-            .map_err(|e| Error::command_error("cmp", format!("Cannot read '{}': {}", file1, e)))?;
+        let contents1 = self.compared_contents("cmp", file1)?;
         let contents2 = fs::read(&path2)
             .map_err(|e| Error::command_error("cmp", format!("Cannot read '{}': {}", file2, e)))?;
 
@@ -196,8 +722,8 @@ impl TestEnvironment {
             let content2_str = String::from_utf8_lossy(&contents2);
             return Err(Error::FileCompare {
                 message: format!(
-                    "Files differ:\n'{}' contains:\n{}\n\n'{}' contains:\n{}",
-                    file1, content1_str, file2, content2_str
+                    "Files differ:\n{}",
+                    unified_diff(file1, file2, &content1_str, &content2_str)
                 ),
             });
         }
@@ -206,6 +732,9 @@ impl TestEnvironment {
     }
 
     /// Compare command output with expected content
+    ///
+    /// The expected pattern is used as-is; callers expand environment
+    /// variables beforehand (see [`TestEnvironment::expand_args`]).
     pub fn compare_output(&self, output_type: &str, expected: &str) -> Result<()> {
         let actual = match self.last_output {
             Some(ref output) => match output_type {
@@ -215,7 +744,7 @@ impl TestEnvironment {
                 "stderr" => String::from_utf8_lossy(&output.stderr)
                     .trim_end()
                     .to_string(),
-                _ => return Err(Error::command_error(output_type, "Unknown output type")),
+                _ => return Err(Error::usage_error(output_type, "Unknown output type")),
             },
             None => {
                 return Err(Error::command_error(
@@ -225,34 +754,25 @@ impl TestEnvironment {
             }
         };
 
-        // Substitute environment variables in the expected pattern
-        let expected_substituted = self.substitute_env_vars(expected);
-
         // Check if expected is a regex pattern (contains regex special characters)
-        if expected_substituted.contains('^')
-            || expected_substituted.contains('$')
-            || expected_substituted.contains('[')
-            || expected_substituted.contains('(')
-            || expected_substituted.contains('*')
-            || expected_substituted.contains('.')
-        {
+        if looks_like_regex(expected) {
             // Enable DOTALL mode (?s) for . to match newlines
             // Enable Unicode mode (?u) for proper Unicode character matching
-            let regex_pattern = format!("(?su){}", expected_substituted);
+            let regex_pattern = format!("(?su){}", expected);
             let regex = Regex::new(&regex_pattern)
-                .map_err(|e| Error::command_error(output_type, format!("Invalid regex: {}", e)))?;
+                .map_err(|e| Error::usage_error(output_type, format!("Invalid regex: {}", e)))?;
 
             if !regex.is_match(&actual) {
                 return Err(Error::OutputCompare {
-                    expected: expected_substituted,
+                    expected: expected.to_string(),
                     actual,
                 });
             }
         } else {
             // Exact string match
-            if actual != expected_substituted {
+            if actual != expected {
                 return Err(Error::OutputCompare {
-                    expected: expected_substituted,
+                    expected: expected.to_string(),
                     actual,
                 });
             }
@@ -276,7 +796,7 @@ impl TestEnvironment {
                 "stderr" => String::from_utf8_lossy(&output.stderr)
                     .trim_end()
                     .to_string(),
-                _ => return Err(Error::command_error(output_type, "Unknown output type")),
+                _ => return Err(Error::usage_error(output_type, "Unknown output type")),
             },
             None => {
                 return Err(Error::command_error(
@@ -286,32 +806,23 @@ impl TestEnvironment {
             }
         };
 
-        // Substitute environment variables in the expected pattern
-        let expected_substituted = self.substitute_env_vars(expected);
-
         // Use regex to count matches
-        let regex_pattern = if expected_substituted.contains('^')
-            || expected_substituted.contains('$')
-            || expected_substituted.contains('[')
-            || expected_substituted.contains('(')
-            || expected_substituted.contains('*')
-            || expected_substituted.contains('.')
-        {
+        let regex_pattern = if looks_like_regex(expected) {
             // Already a regex pattern
-            format!("(?su){}", expected_substituted)
+            format!("(?su){}", expected)
         } else {
             // Treat as literal string and escape for regex
-            format!("(?su){}", regex::escape(&expected_substituted))
+            format!("(?su){}", regex::escape(expected))
         };
 
         let regex = Regex::new(&regex_pattern)
-            .map_err(|e| Error::command_error(output_type, format!("Invalid regex: {}", e)))?;
+            .map_err(|e| Error::usage_error(output_type, format!("Invalid regex: {}", e)))?;
 
         let match_count = regex.find_iter(&actual).count();
 
         if match_count != expected_count {
             return Err(Error::OutputCompare {
-                expected: format!("{} (count: {})", expected_substituted, expected_count),
+                expected: format!("{} (count: {})", expected, expected_count),
                 actual: format!("{} (count: {})", actual, match_count),
             });
         }
@@ -354,52 +865,80 @@ impl TestEnvironment {
         Ok(())
     }
 
-    /// Kill a background process
-    pub fn kill_background_process(&mut self, name: &str, _signal: Option<&str>) -> Result<()> {
-        if let Some(mut child) = self.background_processes.remove(name) {
-            // On Unix, we could send specific signals, but for cross-platform compatibility,
-            // we'll just kill the process
-            child.kill()?;
-            let _ = child.wait()?; // Reap the process
-            Ok(())
-        } else {
-            Err(Error::command_error(
-                "kill",
-                format!("No background process named '{}'", name),
-            ))
-        }
-    }
+    /// Send a signal to a background process, or to all of them if no name
+    /// is given
+    ///
+    /// The signal is a name such as `INT` or `SIGTERM`, or a number, and
+    /// defaults to `KILL`. Processes are not reaped: a later `wait` collects
+    /// their output and checks how they exited.
+    pub fn kill_background_process(
+        &mut self,
+        name: Option<&str>,
+        signal: Option<&str>,
+    ) -> Result<()> {
+        let signal = signal.unwrap_or("KILL");
+        let names = match name {
+            Some(name) if self.background_processes.contains_key(name) => vec![name.to_string()],
+            Some(name) => {
+                return Err(Error::usage_error(
+                    "kill",
+                    format!("No background process named '{}'", name),
+                ))
+            }
+            None => self.background_names(),
+        };
 
-    /// Substitute environment variables in a string
-    pub fn substitute_env_vars(&self, input: &str) -> String {
-        let mut result = input.to_string();
-
-        // First handle ${VAR@R} patterns for regex quoting
-        for (key, value) in &self.env_vars {
-            let regex_quoted_pattern = format!("${{{}@R}}", key);
-            if result.contains(&regex_quoted_pattern) {
-                // Escape regex metacharacters in the value
-                let escaped_value = regex::escape(value);
-                result = result.replace(&regex_quoted_pattern, &escaped_value);
+        for name in names {
+            let process = self
+                .background_processes
+                .get_mut(&name)
+                .expect("process is running");
+            match process::send_signal(&mut process.child, signal) {
+                Some(result) => result.map_err(|e| {
+                    Error::command_error(
+                        "kill",
+                        format!("Cannot signal background process '{}': {}", name, e),
+                    )
+                })?,
+                None => {
+                    return Err(Error::usage_error(
+                        "kill",
+                        format!("Unsupported signal '{}'", signal),
+                    ))
+                }
             }
         }
+        Ok(())
+    }
 
-        // Then handle normal $VAR and ${VAR} patterns
-        for (key, value) in &self.env_vars {
-            let patterns = [
-                format!("${{{}}}", key), // ${VAR}
-                format!("${}", key),     // $VAR (but be careful with word boundaries)
-            ];
+    /// Expand environment variables in a command's arguments
+    ///
+    /// Text that was single-quoted in the script is left untouched.
+    pub fn expand_args(&self, command: &crate::parser::Command) -> Result<Vec<String>> {
+        command
+            .arg_templates
+            .iter()
+            .map(|template| self.substitute_env_vars(template))
+            .collect()
+    }
 
-            for pattern in &patterns {
-                result = result.replace(pattern, value);
-            }
+    /// Look up an environment variable as seen by commands in this environment
+    ///
+    /// Variables set on the environment take precedence over those inherited
+    /// from the host process. In hermetic mode nothing is inherited.
+    pub fn lookup_env_var(&self, key: &str) -> Option<String> {
+        match self.env_vars.get(key) {
+            Some(value) => Some(value.clone()),
+            None if self.hermetic => None,
+            None => std::env::var(key).ok(),
         }
+    }
 
-        // Handle special case: $$ -> $ (escape)
-        result = result.replace("$$", "$");
-
-        result
+    /// Substitute environment variables in a string
+    ///
+    /// See [`expand_vars`] for the supported syntax.
+    pub fn substitute_env_vars(&self, input: &str) -> Result<String> {
+        expand_vars(input, |key| self.lookup_env_var(key))
     }
 
     /// Preserve the work directory by preventing TempDir cleanup
@@ -410,6 +949,171 @@ impl TestEnvironment {
     }
 }
 
+/// Render a command and its arguments for messages
+fn describe_command(cmd: &str, args: &[String]) -> String {
+    std::iter::once(cmd)
+        .chain(args.iter().map(|arg| arg.as_str()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether `stdout` and `stderr` take an expected text as a regex rather
+/// than matching it exactly
+pub(crate) fn looks_like_regex(expected: &str) -> bool {
+    expected.contains(['^', '$', '[', '(', '*', '.'])
+}
+
+/// Quote an argument so it can be pasted into a POSIX shell
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Shorten output for a failure report
+///
+/// Long lines are cut, and only the first and last lines of long output are
+/// kept, with a note saying how many were left out.
+fn truncate_output(text: &str) -> String {
+    let lines: Vec<String> = text
+        .trim_end()
+        .lines()
+        .map(|line| match line.char_indices().nth(REPORT_LINE_CHARS) {
+            Some((end, _)) => format!("{}...", &line[..end]),
+            None => line.to_string(),
+        })
+        .collect();
+
+    if lines.len() <= 2 * REPORT_OUTPUT_LINES {
+        return lines.join("\n");
+    }
+    let omitted = lines.len() - 2 * REPORT_OUTPUT_LINES;
+    format!(
+        "{}\n... ({} lines omitted) ...\n{}",
+        lines[..REPORT_OUTPUT_LINES].join("\n"),
+        omitted,
+        lines[lines.len() - REPORT_OUTPUT_LINES..].join("\n")
+    )
+}
+
+/// Expand environment variable references in `input`
+///
+/// Supported forms:
+/// - `$VAR` - the longest run of `[A-Za-z0-9_]` starting with a letter or `_`
+/// - `${VAR}` - any name up to the closing brace
+/// - `${VAR:-default}` - `default` (itself expanded) if `VAR` is unset or empty
+/// - `${VAR:?message}` - an error if `VAR` is unset or empty
+/// - `${VAR@R}` - the value with regex metacharacters escaped
+/// - `$$` - a literal `$`
+///
+/// Undefined variables expand to the empty string. A `$` that does not start
+/// one of the forms above, and an unterminated `${`, are kept literally.
+pub fn expand_vars<F>(input: &str, lookup: F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    expand_vars_with(input, &lookup)
+}
+
+fn expand_vars_with(input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let mut result = String::with_capacity(input.len());
+    let mut rest = input;
+
+    while let Some(dollar) = rest.find('$') {
+        result.push_str(&rest[..dollar]);
+        let after = &rest[dollar + 1..];
+
+        if let Some(tail) = after.strip_prefix('$') {
+            // $$ escapes a literal dollar sign
+            result.push('$');
+            rest = tail;
+        } else if let Some(braced) = after.strip_prefix('{') {
+            match find_closing_brace(braced) {
+                Some(close) => {
+                    result.push_str(&expand_braced(&braced[..close], lookup)?);
+                    rest = &braced[close + 1..];
+                }
+                None => {
+                    // Unterminated ${ - keep the remainder as-is
+                    result.push('$');
+                    rest = after;
+                }
+            }
+        } else if after.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            let end = after
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(after.len());
+            result.push_str(&lookup(&after[..end]).unwrap_or_default());
+            rest = &after[end..];
+        } else {
+            result.push('$');
+            rest = after;
+        }
+    }
+
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// Find the `}` that closes a `${`, allowing nested `${...}` in defaults
+fn find_closing_brace(input: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut prev = '\0';
+    for (i, c) in input.char_indices() {
+        match c {
+            '{' if prev == '$' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+        prev = c;
+    }
+    None
+}
+
+/// Expand the body of a `${...}` reference
+fn expand_braced(body: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    if let Some(name) = body.strip_suffix("@R").filter(|name| !name.is_empty()) {
+        return Ok(regex::escape(&lookup(name).unwrap_or_default()));
+    }
+
+    // Operators are searched for after the first character so that names
+    // like `:` stay addressable as `${:}`
+    let operator = body
+        .char_indices()
+        .skip(1)
+        .find(|&(i, _)| body[i..].starts_with(":-") || body[i..].starts_with(":?"))
+        .map(|(i, _)| i);
+
+    let Some(i) = operator else {
+        return Ok(lookup(body).unwrap_or_default());
+    };
+
+    let name = &body[..i];
+    let word = &body[i + 2..];
+    match lookup(name).filter(|value| !value.is_empty()) {
+        Some(value) => Ok(value),
+        None if body[i..].starts_with(":-") => expand_vars_with(word, lookup),
+        None => {
+            let message = if word.is_empty() {
+                "parameter null or not set".to_string()
+            } else {
+                expand_vars_with(word, lookup)?
+            };
+            Err(Error::UnsetVariable {
+                name: name.to_string(),
+                message,
+            })
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -422,6 +1126,20 @@ mod tests {
         assert!(env.work_dir.is_dir());
     }
 
+    #[test]
+    fn test_predefined_env_vars() {
+        let env = TestEnvironment::new().unwrap();
+        let expanded = env
+            .substitute_env_vars("a${/}b${:}c $devnull prog$exe")
+            .unwrap();
+
+        if cfg!(windows) {
+            assert_eq!(expanded, "a\\b;c NUL prog.exe");
+        } else {
+            assert_eq!(expanded, "a/b:c /dev/null prog");
+        }
+    }
+
     #[test]
     fn test_setup_files() {
         let mut env = TestEnvironment::new().unwrap();
@@ -462,4 +1180,96 @@ mod tests {
         // Should fail
         assert!(env.compare_files("file1.txt", "file3.txt").is_err());
     }
+
+    fn lookup(key: &str) -> Option<String> {
+        match key {
+            "HOME" => Some("/home/user".to_string()),
+            "HOMEDIR" => Some("/srv/home".to_string()),
+            "EMPTY" => Some(String::new()),
+            "DOTS" => Some("a.b".to_string()),
+            ":" => Some("colon".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand_vars_word_boundaries() {
+        assert_eq!(
+            expand_vars("$HOME $HOMEDIR ${HOME}DIR", lookup).unwrap(),
+            "/home/user /srv/home /home/userDIR"
+        );
+        assert_eq!(expand_vars("$HOME/bin", lookup).unwrap(), "/home/user/bin");
+    }
+
+    #[test]
+    fn test_expand_vars_undefined_and_literals() {
+        assert_eq!(expand_vars("[$MISSING]", lookup).unwrap(), "[]");
+        assert_eq!(expand_vars("${MISSING}x", lookup).unwrap(), "x");
+        assert_eq!(expand_vars("cost $5 $", lookup).unwrap(), "cost $5 $");
+        assert_eq!(expand_vars("$$HOME", lookup).unwrap(), "$HOME");
+        assert_eq!(expand_vars("${HOME", lookup).unwrap(), "${HOME");
+        assert_eq!(expand_vars("${:}", lookup).unwrap(), "colon");
+    }
+
+    #[test]
+    fn test_expand_vars_operators() {
+        assert_eq!(
+            expand_vars("${MISSING:-fallback}", lookup).unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            expand_vars("${EMPTY:-fallback}", lookup).unwrap(),
+            "fallback"
+        );
+        assert_eq!(
+            expand_vars("${HOME:-fallback}", lookup).unwrap(),
+            "/home/user"
+        );
+        assert_eq!(
+            expand_vars("${MISSING:-${HOME}/x}", lookup).unwrap(),
+            "/home/user/x"
+        );
+        assert_eq!(expand_vars("${DOTS@R}", lookup).unwrap(), "a\\.b");
+
+        let err = expand_vars("${MISSING:?must be set}", lookup).unwrap_err();
+        assert_eq!(err.to_string(), "MISSING: must be set");
+        assert!(expand_vars("${HOME:?must be set}", lookup).is_ok());
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("plain-arg.txt"), "plain-arg.txt");
+        assert_eq!(shell_quote("two words"), "'two words'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("$HOME"), "'$HOME'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn test_truncate_output() {
+        assert_eq!(truncate_output("one\ntwo\n"), "one\ntwo");
+
+        let long: Vec<String> = (1..=50).map(|i| format!("line {}", i)).collect();
+        let truncated = truncate_output(&long.join("\n"));
+        assert!(truncated.starts_with("line 1\n"));
+        assert!(truncated.contains("line 10\n... (30 lines omitted) ...\nline 41\n"));
+        assert!(truncated.ends_with("line 50"));
+
+        let wide = "x".repeat(500);
+        assert_eq!(truncate_output(&wide).len(), REPORT_LINE_CHARS + 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_describe_last_command() {
+        let mut env = TestEnvironment::new().unwrap();
+        assert_eq!(env.describe_last_command(), None);
+
+        env.execute_command("sh", &["-c".to_string(), "echo hi; exit 2".to_string()])
+            .unwrap();
+        let description = env.describe_last_command().unwrap();
+        assert!(description.starts_with("Last exec: sh -c 'echo hi; exit 2'\n"));
+        assert!(description.contains(&format!("Working directory: {}", env.work_dir.display())));
+        assert!(description.contains("Exit status: 2\n[stdout]\nhi"));
+    }
 }
@@ -1,10 +1,20 @@
 //! Command execution logic
 
 use crate::error::{Error, Result};
-use crate::parser::Command;
+use crate::parser::condition::Condition;
+use crate::parser::cst::{Edit, Span, SyntaxTree};
+use crate::parser::{parse_file_header, Command, Expectation, TxtarFile};
+use crate::run::environment::looks_like_regex;
+use crate::run::log::ScriptLog;
+use crate::run::process;
 use crate::run::{environment::TestEnvironment, params::RunParams};
 use std::fs;
 use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The longest output update mode writes inline; longer output moves into
+/// an archive file
+const MAX_INLINE_OUTPUT: usize = 80;
 
 /// Information about a script update needed when in update mode
 #[derive(Debug, Clone)]
@@ -15,10 +25,43 @@ pub struct ScriptUpdate {
     pub command_name: String,
     /// The new expected output
     pub new_output: String,
+    /// Whether the output can move into an archive file that `cmp` finds
+    /// equal to it
+    pub fits_archive_file: bool,
+    /// Whether the output names a file in the work directory, which
+    /// `stdout` and `stderr` would read instead of taking it as text
+    pub names_file: bool,
+}
+
+/// A file in the script's archive whose contents must be replaced in update
+/// mode, after a `cmp` or `cmpenv` against it failed
+#[derive(Debug, Clone)]
+pub struct FileUpdate {
+    /// The name in the file's `-- name --` header
+    pub name: String,
+    /// The new contents, which the comparison expected
+    pub contents: String,
+}
+
+/// How a script finished when it did not fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScriptOutcome {
+    /// The script ran to completion
+    Passed,
+    /// The script ended early with `skip`
+    Skipped {
+        /// The message given to `skip`
+        reason: String,
+    },
+    /// The script ended early with `stop`, which counts as passing
+    Stopped {
+        /// The message given to `stop`
+        reason: String,
+    },
 }
 
 /// Run a single script with the given parameters - main implementation
-pub fn run_script_impl(script_path: &Path, params: &RunParams) -> Result<()> {
+pub fn run_script_impl(script_path: &Path, params: &RunParams) -> Result<ScriptOutcome> {
     // Read and parse the script
     let content = fs::read_to_string(script_path)?;
     let script = crate::parser::parse(&content).map_err(|e| {
@@ -37,6 +80,11 @@ pub fn run_script_impl(script_path: &Path, params: &RunParams) -> Result<()> {
     // Create script context for better error reporting
     let script_file = script_path.to_string_lossy().to_string();
 
+    // Reject unknown conditions before running anything
+    if let Some((line_num, e)) = check_conditions(&script.commands, params) {
+        return Err(Error::script_error(&script_file, line_num, &content, e));
+    }
+
     // Create test environment
     let mut env = TestEnvironment::new_with_root(params.workdir_root.as_deref())?;
 
@@ -47,6 +95,15 @@ pub fn run_script_impl(script_path: &Path, params: &RunParams) -> Result<()> {
     let work_dir_str = env.work_dir.to_string_lossy().to_string();
     env.set_env_var("WORK", &work_dir_str);
 
+    // Replace the inherited host environment with a fixed baseline
+    if params.hermetic {
+        env.make_hermetic(&params.passthrough_env)?;
+    }
+
+    // Apply configured timeouts; the script deadline starts now
+    env.command_timeout = params.command_timeout;
+    env.set_script_timeout(params.script_timeout);
+
     // Run setup hook if provided
     if let Some(setup) = &params.setup {
         setup(&mut env)?;
@@ -54,12 +111,40 @@ pub fn run_script_impl(script_path: &Path, params: &RunParams) -> Result<()> {
 
     // Track script updates if we're in update mode
     let mut updates = Vec::new();
+    let mut file_updates = Vec::new();
+
+    // Failures collected in continue-on-failure mode
+    let mut failures = Vec::new();
+
+    // Log of the run, split into sections by the script's comments
+    let mut log = ScriptLog::new(params.verbose);
+    let mut comments = script.comments.iter().peekable();
+    let lines: Vec<&str> = content.lines().collect();
 
     // Execute commands
     for command in &script.commands {
-        let result = execute_command(&mut env, command, params);
+        while let Some(comment) = comments.next_if(|c| c.line_num < command.line_num) {
+            log.section(&comment.text);
+        }
+        log.command(lines[command.line_num - 1].trim());
 
-        if let Err(e) = result {
+        let result = match env.script_deadline {
+            // Don't start new commands once the script has run out of time
+            Some((deadline, timeout)) if Instant::now() >= deadline => Err(Error::Timeout {
+                command: command.name.clone(),
+                timeout,
+                stdout: String::new(),
+                stderr: String::new(),
+            }),
+            _ => execute_logged(&mut env, command, params, &mut log),
+        };
+
+        // Check for early termination; the outcome is reported below
+        if env.should_skip {
+            break;
+        }
+
+        if let Err(mut e) = result {
             // If we're in update mode and this is an output comparison error, capture the update
             if params.update_scripts {
                 if let Error::OutputCompare {
@@ -67,277 +152,603 @@ pub fn run_script_impl(script_path: &Path, params: &RunParams) -> Result<()> {
                     actual,
                 } = &e
                 {
-                    if command.name == "stdout" || command.name == "stderr" {
+                    let counted = command.args.iter().any(|arg| arg.starts_with("-count="));
+                    if (command.name == "stdout" || command.name == "stderr") && !counted {
                         updates.push(ScriptUpdate {
                             line_num: command.line_num,
                             command_name: command.name.clone(),
                             new_output: actual.clone(),
+                            fits_archive_file: fits_archive_file(&env, &command.name, actual),
+                            names_file: !actual.is_empty() && env.work_dir.join(actual).exists(),
                         });
                         // Continue instead of failing
                         continue;
                     }
                 }
+                if let Error::FileCompare { message } = &mut e {
+                    match archive_file_update(&env, command, &script.files) {
+                        Some(Ok(update)) => {
+                            file_updates.retain(|u: &FileUpdate| u.name != update.name);
+                            file_updates.push(update);
+                            continue;
+                        }
+                        // Say why the file was left alone
+                        Some(Err(reason)) => {
+                            message.push('\n');
+                            message.push_str(&reason);
+                        }
+                        None => {}
+                    }
+                }
             }
 
-            // Handle work directory preservation on failure
-            if params.preserve_work_on_failure {
-                let preserved_path = env.preserve_work_dir();
-                eprintln!(
-                    "Test failed. Work directory preserved at: {}",
-                    preserved_path.display()
-                );
-                eprintln!("You can inspect the test environment:");
-                eprintln!("  cd {}", preserved_path.display());
-                eprintln!("  ls -la");
-            }
+            log.failure(&format!("{}:{}", script_file, command.line_num), &e);
 
             // Wrap error with script context for non-update cases or non-output errors
-            return Err(Error::script_error(
-                &script_file,
-                command.line_num,
-                &content,
-                e,
-            ));
-        }
+            let error = Error::script_error(&script_file, command.line_num, &content, e)
+                .with_last_command(env.describe_last_command());
 
-        // Check for early termination
-        if env.should_skip {
-            // Handle work directory preservation for skipped tests if configured
+            // Keep going if requested
+            if params.continue_on_failure {
+                failures.push(error);
+                // Past the script deadline every later command would fail too
+                if env
+                    .script_deadline
+                    .is_some_and(|(deadline, _)| Instant::now() >= deadline)
+                {
+                    break;
+                }
+                continue;
+            }
+
+            // Handle work directory preservation on failure
             if params.preserve_work_on_failure {
-                let preserved_path = env.preserve_work_dir();
-                eprintln!(
-                    "Test skipped. Work directory preserved at: {}",
-                    preserved_path.display()
-                );
-                eprintln!("You can inspect the test environment:");
-                eprintln!("  cd {}", preserved_path.display());
-                eprintln!("  ls -la");
+                report_preserved_work_dir(env, "Test failed.");
             }
-            return Err(Error::Generic("Test skipped".to_string()));
+            return Err(with_log(error, log));
         }
+
         if env.should_stop {
             break; // Stop early but don't fail
         }
     }
 
+    // Comments after the last command still head (empty) sections
+    if !env.should_stop && !env.should_skip {
+        for comment in comments {
+            log.section(&comment.text);
+        }
+    }
+
     // Apply updates if any were collected
-    if !updates.is_empty() && params.update_scripts {
-        apply_script_updates(script_path, &content, &updates)?;
+    if (!updates.is_empty() || !file_updates.is_empty()) && params.update_scripts {
+        apply_script_updates(script_path, &content, &updates, &file_updates)?;
     }
 
-    // Wait for any remaining background processes - handle failures here too
-    let background_names: Vec<String> = env.background_processes.keys().cloned().collect();
-    for name in background_names {
-        if let Err(e) = env.wait_for_background(&name) {
-            if params.preserve_work_on_failure {
-                let preserved_path = env.preserve_work_dir();
-                eprintln!("Test failed during background process cleanup. Work directory preserved at: {}", preserved_path.display());
-                eprintln!("You can inspect the test environment:");
-                eprintln!("  cd {}", preserved_path.display());
-                eprintln!("  ls -la");
-            }
-            return Err(e);
+    // Kill any background processes the script didn't wait for
+    env.stop_background_processes();
+
+    // Report every failure collected in continue-on-failure mode
+    if !failures.is_empty() {
+        if params.preserve_work_on_failure {
+            report_preserved_work_dir(env, "Test failed.");
         }
+        return Err(with_log(
+            Error::multiple_failures(&script_file, failures),
+            log,
+        ));
     }
 
-    Ok(())
+    if env.should_skip {
+        let reason = env
+            .exit_reason
+            .take()
+            .unwrap_or_else(|| "Test skipped".to_string());
+
+        // Handle work directory preservation for skipped tests if configured
+        if params.preserve_work_on_failure {
+            report_preserved_work_dir(env, "Test skipped.");
+        }
+        print_verbose_log(script_path, params, log);
+        return Ok(ScriptOutcome::Skipped { reason });
+    }
+
+    print_verbose_log(script_path, params, log);
+
+    if env.should_stop {
+        let reason = env
+            .exit_reason
+            .take()
+            .unwrap_or_else(|| "Test stopped early".to_string());
+        return Ok(ScriptOutcome::Stopped { reason });
+    }
+
+    Ok(ScriptOutcome::Passed)
+}
+
+/// Execute a command, logging any output it produces
+fn execute_logged(
+    env: &mut TestEnvironment,
+    command: &Command,
+    params: &RunParams,
+    log: &mut ScriptLog,
+) -> Result<()> {
+    if (command.name == "exec" && !command.background)
+        || command.name == "wait"
+        || command.name == "bgoutput"
+        || command.name == "waitoutput"
+    {
+        // These always replace the last output when they produce one, so
+        // any output present afterwards is theirs
+        let previous = env.last_output.take();
+        let result = execute_command(env, command, params);
+        match &env.last_output {
+            Some(output) if env.last_output_running => log.running_output(output),
+            Some(output) => log.output(output),
+            None => env.last_output = previous,
+        }
+        result
+    } else if params.commands.contains_key(&command.name) {
+        // Custom commands may set the last output themselves
+        let previous = env.last_output.clone();
+        let result = execute_command(env, command, params);
+        if let Some(output) = env
+            .last_output
+            .as_ref()
+            .filter(|o| Some(*o) != previous.as_ref())
+        {
+            env.last_output_running = false;
+            log.output(output);
+        }
+        result
+    } else {
+        execute_command(env, command, params)
+    }
+}
+
+/// Attach the script's execution log to a failure
+fn with_log(error: Error, log: ScriptLog) -> Error {
+    Error::WithLog {
+        log: log.finish(),
+        source: Box::new(error),
+    }
+}
+
+/// Print the execution log of a script that didn't fail, in verbose mode
+///
+/// Failed scripts carry their log in the error instead.
+fn print_verbose_log(script_path: &Path, params: &RunParams, log: ScriptLog) {
+    if params.verbose {
+        let name = script_path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        print!("** RUN {} **\n{}", name, log.finish());
+    }
+}
+
+/// Preserve the work directory and tell the user where to find it
+fn report_preserved_work_dir(env: TestEnvironment, reason: &str) {
+    let preserved_path = env.preserve_work_dir();
+    eprintln!(
+        "{} Work directory preserved at: {}",
+        reason,
+        preserved_path.display()
+    );
+    eprintln!("You can inspect the test environment:");
+    eprintln!("  cd {}", preserved_path.display());
+    eprintln!("  ls -la");
+}
+
+/// The update for a failed `cmp` or `cmpenv` whose expected file came from
+/// the script's archive
+///
+/// Files the script created itself can't be updated, so the comparison fails
+/// as usual. Contents an archive file can't hold exactly are refused with
+/// the reason, as the updated script would still fail.
+fn archive_file_update(
+    env: &TestEnvironment,
+    command: &Command,
+    files: &[TxtarFile],
+) -> Option<std::result::Result<FileUpdate, String>> {
+    if command.name != "cmp" && command.name != "cmpenv" {
+        return None;
+    }
+    let args = env.expand_args(command).ok()?;
+    let [actual, expected] = args.as_slice() else {
+        return None;
+    };
+
+    let expected_path = env.work_dir.join(expected);
+    let file = files
+        .iter()
+        .find(|file| env.work_dir.join(&file.name) == expected_path)?;
+    let contents = env.compared_contents(&command.name, actual).ok()?;
+
+    let refused = |reason: &str| {
+        Some(Err(format!(
+            "Update mode can't rewrite '{}': {}",
+            file.name, reason
+        )))
+    };
+    let Ok(contents) = String::from_utf8(contents) else {
+        return refused("the new contents aren't valid UTF-8");
+    };
+    let contents = if command.name == "cmpenv" {
+        // cmpenv ignores surrounding whitespace and expands the expected file
+        contents.trim().replace('$', "$$")
+    } else {
+        contents
+    };
+    if !archive_can_hold(&contents) {
+        return refused(
+            "an archive file can't hold blank lines, a final line ending or a \
+             line that looks like a file header",
+        );
+    }
+
+    Some(Ok(FileUpdate {
+        name: file.name.clone(),
+        contents,
+    }))
+}
+
+/// Whether an archive file can hold `contents` exactly
+///
+/// Archive files lose blank lines and their final line ending, and a line
+/// that looks like a header would split the file.
+fn archive_can_hold(contents: &str) -> bool {
+    contents.is_empty()
+        || contents.split('\n').all(|line| {
+            !line.trim().is_empty() && !line.ends_with('\r') && parse_file_header(line).is_none()
+        })
+}
+
+/// Whether `output`, the trimmed `stream` a comparison saw, can move into an
+/// archive file that `cmp` finds equal to the stream
+///
+/// Besides what [`archive_can_hold`] rules out, `cmp` sees the trailing
+/// whitespace that `stdout` and `stderr` ignore.
+fn fits_archive_file(env: &TestEnvironment, stream: &str, output: &str) -> bool {
+    env.compared_contents(stream, stream)
+        .is_ok_and(|contents| contents == output.as_bytes())
+        && archive_can_hold(output)
 }
 
 /// Apply script updates to the actual file
-fn apply_script_updates(script_path: &Path, content: &str, updates: &[ScriptUpdate]) -> Result<()> {
-    let lines: Vec<&str> = content.lines().collect();
-    let mut updated_lines = Vec::new();
-
-    let mut i = 0;
-    while i < lines.len() {
-        let line_num = i + 1; // Line numbers are 1-based
-
-        // Check if this line needs to be updated
-        if let Some(update) = updates.iter().find(|u| u.line_num == line_num) {
-            // This is a stdout/stderr command that needs updating
-            let line = lines[i];
-            let cmd_part = format!("{} ", update.command_name);
-
-            if line.trim_start().starts_with(&cmd_part) {
-                // Extract the indentation from the original line
-                let indent = line.len() - line.trim_start().len();
-                let indent_str = " ".repeat(indent);
-
-                // Create the updated line with proper quoting
-                let quoted_output = if update.new_output.contains(' ')
-                    || update.new_output.contains('\n')
-                    || update.new_output.contains('"')
-                {
-                    // Use proper shell quoting for complex strings
-                    format!("\"{}\"", update.new_output.replace('"', "\\\""))
-                } else if update.new_output.is_empty() {
-                    "\"-\"".to_string()
-                } else {
-                    update.new_output.clone()
-                };
+///
+/// Only the expected text of each `stdout`/`stderr` command and the bodies
+/// of updated archive files change; everything else is kept byte for byte.
+/// Multi-line or long output that fits an archive file moves into a new one,
+/// compared with `cmp`.
+fn apply_script_updates(
+    script_path: &Path,
+    content: &str,
+    updates: &[ScriptUpdate],
+    file_updates: &[FileUpdate],
+) -> Result<()> {
+    let mut tree = SyntaxTree::parse(content)?;
+    let mut edits = Vec::new();
+    let mut new_files: Vec<String> = Vec::new();
 
-                updated_lines.push(format!(
-                    "{}{} {}",
-                    indent_str, update.command_name, quoted_output
-                ));
-            } else {
-                // Shouldn't happen, but preserve the original line if it doesn't match
-                updated_lines.push(line.to_string());
-            }
-        } else {
-            // Keep the original line
-            updated_lines.push(lines[i].to_string());
+    for update in updates {
+        let Some(line) = tree.command_at(update.line_num) else {
+            continue;
+        };
+        let output = &update.new_output;
+
+        if update.fits_archive_file && (output.contains('\n') || output.len() > MAX_INLINE_OUTPUT) {
+            let name = unused_file_name(&tree, &new_files, &update.command_name);
+            let end = line.args.last().map_or(line.name.end, |arg| arg.end);
+            edits.push(Edit::new(
+                Span::new(line.name.start, end),
+                format!("cmp {} {}", update.command_name, name),
+            ));
+            edits.push(tree.append_file_edit(&name, output));
+            new_files.push(name);
+        } else if let Some(pattern) = line.args.first() {
+            edits.push(Edit::new(
+                *pattern,
+                expected_output_literal(output, update.names_file),
+            ));
         }
-        i += 1;
     }
 
-    // Write the updated content back to the file
-    let updated_content = updated_lines.join("\n");
-    if updated_content != content {
-        fs::write(script_path, updated_content)?;
+    for update in file_updates {
+        edits.extend(tree.file_contents_edit(&update.name, &update.contents));
+    }
+
+    tree.apply(edits)?;
+    if tree.text() != content {
+        fs::write(script_path, tree.text())?;
     }
 
     Ok(())
 }
 
+/// A name for a new archive file holding a stream's output: `stdout.txt`,
+/// or `stdout-2.txt`, `stdout-3.txt`, ... if that is taken
+fn unused_file_name(tree: &SyntaxTree, new_files: &[String], stream: &str) -> String {
+    let taken = |name: &String| tree.file(name).is_some() || new_files.contains(name);
+    std::iter::once(format!("{}.txt", stream))
+        .chain((2..).map(|n| format!("{}-{}.txt", stream, n)))
+        .find(|name| !taken(name))
+        .expect("some suffix is free")
+}
+
+/// Write output as the expected text of a `stdout` or `stderr` command
+///
+/// Output the comparison would take for a regex becomes an anchored regex
+/// matching it literally. So does output that would read as something else:
+/// `-` (no output), a `-count=` flag, a file name (`names_file`) or, starting
+/// with `&`, a background marker. The text is quoted so that the script
+/// reads it back unchanged, with any `$` kept from expanding.
+fn expected_output_literal(output: &str, names_file: bool) -> String {
+    let misread = output == "-" || output.starts_with("-count=") || output.starts_with('&');
+    if looks_like_regex(output) || misread || names_file {
+        quote_argument(&format!("^{}$", escape_regex(output)))
+    } else {
+        quote_argument(output)
+    }
+}
+
+/// Escape the characters that have a meaning in a regex
+fn escape_regex(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        if "\\.+*?()|[]{}^$".contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// Quote a script argument so that it reads back as `text`
+///
+/// Plain words stay bare; anything else is double-quoted, or single-quoted
+/// if it holds a `$`, which only single quotes keep from expanding.
+fn quote_argument(text: &str) -> String {
+    let special = |ch: char| " \t\r\n\"'\\$".contains(ch);
+    if !text.is_empty() && !text.contains(special) {
+        return text.to_string();
+    }
+
+    let quote = if text.contains('$') { '\'' } else { '"' };
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push(quote);
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            // A backslash only needs escaping where it would start an escape
+            '\\' => match chars.peek() {
+                None | Some('\\' | '\'' | '"' | 'n' | 't' | 'r') => quoted.push_str("\\\\"),
+                Some(_) => quoted.push('\\'),
+            },
+            _ if ch == quote => {
+                quoted.push('\\');
+                quoted.push(ch);
+            }
+            _ => quoted.push(ch),
+        }
+    }
+    quoted.push(quote);
+    quoted
+}
+
+/// Parse a duration such as `500ms`, `30s`, `1.5m` or `1h`
+///
+/// A bare number is taken as seconds.
+pub(crate) fn parse_duration(value: &str) -> Option<Duration> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "" | "s" => number,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
 /// Execute a single command
+///
+/// Commands whose condition isn't met are skipped. Otherwise the command's
+/// result is checked against its expectation: a `!` command must fail and a
+/// `?` command may fail, though misuse (see [`Error::is_negatable`]) always
+/// fails. Background commands are checked when they are waited for instead.
 fn execute_command(env: &mut TestEnvironment, command: &Command, params: &RunParams) -> Result<()> {
-    // For negated commands, we expect them to fail
+    if let Some(ref condition) = command.condition {
+        if !condition_met(condition, env, params)? {
+            return Ok(()); // Skip this command
+        }
+    }
+
     let result = execute_command_inner(env, command, params);
+    if command.background {
+        return result;
+    }
 
-    if command.negated {
-        match result {
-            Ok(_) => Err(Error::command_error(
-                &command.name,
-                "Command was expected to fail but succeeded",
-            )),
-            Err(_) => Ok(()), // Negated command failed as expected
-        }
-    } else {
-        result
+    match (command.expectation, result) {
+        (Expectation::Failure, Ok(())) => Err(Error::command_error(
+            &command.name,
+            "Command was expected to fail but succeeded",
+        )),
+        // The command failed as allowed, unless it was misused
+        (Expectation::Failure | Expectation::Either, Err(e)) if e.is_negatable() => Ok(()),
+        (_, result) => result,
     }
 }
 
+/// Evaluate a command's `[condition]` prefix
+///
+/// Names set with [`RunParams::condition`] take precedence over providers.
+fn condition_met(condition: &Condition, env: &TestEnvironment, params: &RunParams) -> Result<bool> {
+    condition.evaluate(&mut |name: &str| {
+        if let Some(value) = params.conditions.get(name) {
+            return Ok(*value);
+        }
+        match params.condition_provider(name) {
+            Some((provider, arg)) => provider.check(arg, env),
+            None => Err(Error::UnknownCondition {
+                condition: name.to_string(),
+            }),
+        }
+    })
+}
+
+/// Whether a condition name is one `condition_met` can evaluate
+fn condition_known(name: &str, params: &RunParams) -> bool {
+    params.conditions.contains_key(name) || params.condition_provider(name).is_some()
+}
+
+/// Find the first command whose condition uses an unknown name
+fn check_conditions(commands: &[Command], params: &RunParams) -> Option<(usize, Error)> {
+    commands.iter().find_map(|command| {
+        let condition = command.condition.as_ref()?;
+        let name = condition
+            .names()
+            .into_iter()
+            .find(|name| !condition_known(name, params))?;
+        Some((
+            command.line_num,
+            Error::UnknownCondition {
+                condition: name.to_string(),
+            },
+        ))
+    })
+}
+
 /// Inner command execution logic
 fn execute_command_inner(
     env: &mut TestEnvironment,
     command: &Command,
     params: &RunParams,
 ) -> Result<()> {
-    // Check condition if present
-    if let Some(ref condition) = command.condition {
-        let condition_met = if let Some(value) = params.conditions.get(condition) {
-            *value
-        } else if condition.starts_with("env:") {
-            RunParams::check_env_condition(condition)
-        } else if let Some(program) = condition.strip_prefix("exec:") {
-            RunParams::program_exists(program)
-        } else if let Some(base_condition) = condition.strip_prefix('!') {
-            // Handle negated conditions
-            if let Some(value) = params.conditions.get(base_condition) {
-                !value
-            } else if base_condition.starts_with("env:") {
-                // Dynamic negated environment variable condition
-                !RunParams::check_env_condition(base_condition)
-            } else if let Some(program) = base_condition.strip_prefix("exec:") {
-                !RunParams::program_exists(program)
-            } else {
-                return Err(Error::UnknownCondition {
-                    condition: base_condition.to_string(),
-                });
-            }
-        } else {
-            return Err(Error::UnknownCondition {
-                condition: condition.clone(),
-            });
-        };
-
-        if !condition_met {
-            return Ok(()); // Skip this command
-        }
-    }
+    // Expand environment variables in every argument before dispatch
+    let args = env.expand_args(command)?;
 
     // Check for custom commands first
     if let Some(custom_fn) = params.commands.get(&command.name) {
-        return custom_fn(env, &command.args);
+        return custom_fn(env, &args);
     }
 
     // Handle built-in commands
     match command.name.as_str() {
         "exec" => {
-            if command.args.is_empty() {
-                return Err(Error::command_error("exec", "No command specified"));
+            // An optional -exit=N flag asserts the exact exit code
+            let (expected_code, args) = match args.split_first() {
+                Some((flag, rest)) if flag.starts_with("-exit=") => {
+                    let code = flag["-exit=".len()..].parse::<i32>().map_err(|_| {
+                        Error::usage_error("exec", format!("Invalid exit code in '{}'", flag))
+                    })?;
+                    (Some(code), rest)
+                }
+                _ => (None, &args[..]),
+            };
+
+            if args.is_empty() {
+                return Err(Error::usage_error("exec", "No command specified"));
             }
 
-            let cmd = &command.args[0];
-            let args = &command.args[1..];
+            let cmd = &args[0];
+            let args = &args[1..];
 
             if command.background {
-                // Use the command name as the background process name
-                let process_name = cmd.to_string();
-                env.execute_background_command(&process_name, cmd, args)?;
+                if expected_code.is_some() {
+                    return Err(Error::usage_error(
+                        "exec",
+                        "-exit cannot be used with background commands",
+                    ));
+                }
+                // Unnamed processes are named after the program
+                let process_name = match &command.background_name {
+                    Some(name) => name.clone(),
+                    None => env.unique_background_name(cmd),
+                };
+                env.execute_background_command(&process_name, cmd, args, command.expectation)?;
             } else {
                 let output = env.execute_command(cmd, args)?;
+                let stderr = String::from_utf8_lossy(&output.stderr);
 
                 // Check exit status
-                if !output.status.success() {
-                    let stderr = String::from_utf8_lossy(&output.stderr);
-                    return Err(Error::command_error(
-                        "exec",
-                        format!(
-                            "Command '{}' failed with exit code {}: {}",
-                            cmd,
-                            output.status.code().unwrap_or(-1),
-                            stderr.trim()
-                        ),
-                    ));
+                match expected_code {
+                    Some(code) if output.status.code() != Some(code) => {
+                        return Err(Error::command_error(
+                            "exec",
+                            format!(
+                                "Command '{}' ended with {}, expected exit code {}: {}",
+                                cmd,
+                                process::describe_exit(&output.status),
+                                code,
+                                stderr.trim()
+                            ),
+                        ));
+                    }
+                    None if !output.status.success() => {
+                        return Err(Error::command_error(
+                            "exec",
+                            format!(
+                                "Command '{}' failed with {}: {}",
+                                cmd,
+                                process::describe_exit(&output.status),
+                                stderr.trim()
+                            ),
+                        ));
+                    }
+                    _ => {}
                 }
             }
         }
         "cmp" => {
-            if command.args.len() != 2 {
-                return Err(Error::command_error("cmp", "Expected exactly 2 arguments"));
+            if args.len() != 2 {
+                return Err(Error::usage_error("cmp", "Expected exactly 2 arguments"));
             }
-            env.compare_files(&command.args[0], &command.args[1])?;
+            env.compare_files(&args[0], &args[1])?;
         }
         "cmpenv" => {
-            if command.args.len() != 2 {
-                return Err(Error::command_error(
-                    "cmpenv",
-                    "Expected exactly 2 arguments",
-                ));
+            if args.len() != 2 {
+                return Err(Error::usage_error("cmpenv", "Expected exactly 2 arguments"));
             }
-            env.compare_files_with_env(&command.args[0], &command.args[1])?;
+            env.compare_files_with_env(&args[0], &args[1])?;
         }
         "stdout" | "stderr" => {
             // Parse arguments to handle -count=N option
             let mut count_option: Option<usize> = None;
             let mut pattern_arg = None;
 
-            for arg in &command.args {
+            for arg in &args {
                 if let Some(count_str) = arg.strip_prefix("-count=") {
                     // Skip "-count="
                     count_option =
                         Some(count_str.parse::<usize>().map_err(|_| {
-                            Error::command_error(&command.name, "Invalid count value")
+                            Error::usage_error(&command.name, "Invalid count value")
                         })?);
                 } else if pattern_arg.is_none() {
                     pattern_arg = Some(arg);
                 } else {
-                    return Err(Error::command_error(&command.name, "Too many arguments"));
+                    return Err(Error::usage_error(&command.name, "Too many arguments"));
                 }
             }
 
             let expected = pattern_arg
-                .ok_or_else(|| Error::command_error(&command.name, "Expected pattern argument"))?;
+                .ok_or_else(|| Error::usage_error(&command.name, "Expected pattern argument"))?;
 
             // Check if argument is a filename or literal text
             let expected_content = if expected == "-" {
                 // Empty string
                 "".to_string()
             } else if let Ok(file_content) = fs::read_to_string(env.work_dir.join(expected)) {
-                // It's a file - use its contents
-                file_content.trim_end().to_string()
+                // It's a file - use its contents, expanding any variables it references
+                env.substitute_env_vars(file_content.trim_end())?
             } else {
                 // It's literal text
                 expected.to_string()
@@ -350,36 +761,65 @@ fn execute_command_inner(
             }
         }
         "cd" => {
-            if command.args.len() != 1 {
-                return Err(Error::command_error("cd", "Expected exactly 1 argument"));
+            if args.len() != 1 {
+                return Err(Error::usage_error("cd", "Expected exactly 1 argument"));
             }
-            env.change_directory(&command.args[0])?;
+            env.change_directory(&args[0])?;
         }
-        "wait" => {
-            if command.args.len() != 1 {
-                return Err(Error::command_error("wait", "Expected exactly 1 argument"));
+        "wait" => match args.as_slice() {
+            [] => {
+                env.wait_for_all_background()?;
+            }
+            [name] => {
+                env.wait_for_background(name)?;
+            }
+            _ => return Err(Error::usage_error("wait", "Expected at most 1 argument")),
+        },
+        "bgoutput" => {
+            if args.len() != 1 {
+                return Err(Error::usage_error(
+                    "bgoutput",
+                    "Expected exactly 1 argument",
+                ));
             }
-            env.wait_for_background(&command.args[0])?;
+            env.background_output(&args[0])?;
+        }
+        "waitoutput" => {
+            let (name, stream, pattern, timeout) = match args.as_slice() {
+                [name, stream, pattern] => (name, stream, pattern, None),
+                [name, stream, pattern, timeout] => (name, stream, pattern, Some(timeout)),
+                _ => {
+                    return Err(Error::usage_error(
+                        "waitoutput",
+                        "Expected 3 or 4 arguments: name stdout|stderr regex [timeout]",
+                    ))
+                }
+            };
+            let timeout = timeout
+                .map(|value| {
+                    parse_duration(value).ok_or_else(|| {
+                        Error::usage_error("waitoutput", format!("Invalid duration: {}", value))
+                    })
+                })
+                .transpose()?;
+            env.wait_for_output(name, stream, pattern, timeout)?;
         }
         "exists" => {
-            if command.args.is_empty() {
-                return Err(Error::command_error(
-                    "exists",
-                    "Expected at least 1 argument",
-                ));
+            if args.is_empty() {
+                return Err(Error::usage_error("exists", "Expected at least 1 argument"));
             }
 
             // Check for -readonly flag
-            let (check_readonly, files) = if command.args[0] == "-readonly" {
-                if command.args.len() < 2 {
-                    return Err(Error::command_error(
+            let (check_readonly, files) = if args[0] == "-readonly" {
+                if args.len() < 2 {
+                    return Err(Error::usage_error(
                         "exists",
                         "Expected file argument after -readonly",
                     ));
                 }
-                (true, &command.args[1..])
+                (true, &args[1..])
             } else {
-                (false, &command.args[..])
+                (false, &args[..])
             };
 
             for path in files {
@@ -399,47 +839,44 @@ fn execute_command_inner(
             }
         }
         "mkdir" => {
-            if command.args.is_empty() {
-                return Err(Error::command_error(
-                    "mkdir",
-                    "Expected at least 1 argument",
-                ));
+            if args.is_empty() {
+                return Err(Error::usage_error("mkdir", "Expected at least 1 argument"));
             }
-            env.create_directories(&command.args)?;
+            env.create_directories(&args)?;
         }
         "cp" => {
-            if command.args.len() < 2 {
-                return Err(Error::command_error("cp", "Expected at least 2 arguments"));
+            if args.len() < 2 {
+                return Err(Error::usage_error("cp", "Expected at least 2 arguments"));
             }
-            env.copy_files(&command.args)?;
+            env.copy_files(&args)?;
         }
         "rm" => {
-            if command.args.is_empty() {
-                return Err(Error::command_error("rm", "Expected at least 1 argument"));
+            if args.is_empty() {
+                return Err(Error::usage_error("rm", "Expected at least 1 argument"));
             }
-            env.remove_files(&command.args)?;
+            env.remove_files(&args)?;
         }
         "mv" => {
-            if command.args.len() != 2 {
-                return Err(Error::command_error("mv", "Expected exactly 2 arguments"));
+            if args.len() != 2 {
+                return Err(Error::usage_error("mv", "Expected exactly 2 arguments"));
             }
-            env.move_file(&command.args[0], &command.args[1])?;
+            env.move_file(&args[0], &args[1])?;
         }
         "env" => {
-            if command.args.is_empty() {
+            if args.is_empty() {
                 // Print current environment for debugging
                 for (key, value) in &env.env_vars {
                     println!("{}={}", key, value);
                 }
             } else {
                 // Set environment variables
-                for arg in &command.args {
+                for arg in &args {
                     if let Some(eq_pos) = arg.find('=') {
                         let key = &arg[..eq_pos];
                         let value = &arg[eq_pos + 1..];
                         env.set_env_var(key, value);
                     } else {
-                        return Err(Error::command_error(
+                        return Err(Error::usage_error(
                             "env",
                             format!("Invalid env format: {}", arg),
                         ));
@@ -448,76 +885,105 @@ fn execute_command_inner(
             }
         }
         "stdin" => {
-            if command.args.len() != 1 {
-                return Err(Error::command_error("stdin", "Expected exactly 1 argument"));
+            if args.len() != 1 {
+                return Err(Error::usage_error("stdin", "Expected exactly 1 argument"));
             }
-            env.set_stdin_from_file(&command.args[0])?;
+            env.set_stdin_from_file(&args[0])?;
         }
         "skip" => {
+            // Skipping is not a failure - the runner reports the script as skipped
             env.should_skip = true;
-            let message = if command.args.is_empty() {
+            env.exit_reason = Some(if args.is_empty() {
                 "Test skipped".to_string()
             } else {
-                command.args.join(" ")
-            };
-            return Err(Error::Generic(format!("SKIP: {}", message)));
+                args.join(" ")
+            });
         }
         "stop" => {
             env.should_stop = true;
-            let _message = if command.args.is_empty() {
+            env.exit_reason = Some(if args.is_empty() {
                 "Test stopped early".to_string()
             } else {
-                command.args.join(" ")
-            };
+                args.join(" ")
+            });
             // For stop, we don't return an error - the test passes but stops
             return Ok(());
         }
-        "kill" => {
-            let (signal, target) = if command.args.len() == 2 && command.args[0].starts_with('-') {
-                (Some(command.args[0].as_str()), &command.args[1])
-            } else if command.args.len() == 1 {
-                (None, &command.args[0])
+        "timeout" => {
+            // Directive overriding this script's timeouts from here on:
+            //   timeout DURATION           - the rest of the script
+            //   timeout -command DURATION  - each following command
+            // A duration of 0 removes the limit.
+            let (per_command, value) = match args.as_slice() {
+                [flag, value] if flag == "-command" => (true, value),
+                [value] => (false, value),
+                _ => {
+                    return Err(Error::usage_error(
+                        "timeout",
+                        "Usage: timeout [-command] DURATION",
+                    ))
+                }
+            };
+            let duration = parse_duration(value).ok_or_else(|| {
+                Error::usage_error("timeout", format!("Invalid duration: {}", value))
+            })?;
+            let limit = Some(duration).filter(|d| !d.is_zero());
+
+            if per_command {
+                env.command_timeout = limit;
             } else {
-                return Err(Error::command_error("kill", "Expected 1 or 2 arguments"));
+                env.set_script_timeout(limit);
+            }
+        }
+        "kill" => {
+            let (signal, rest) = match args.split_first() {
+                Some((flag, rest)) if flag.starts_with('-') => (Some(&flag[1..]), rest),
+                _ => (None, &args[..]),
             };
-            env.kill_background_process(target, signal)?;
+            if rest.len() > 1 {
+                return Err(Error::usage_error(
+                    "kill",
+                    "Expected at most a signal and a process name",
+                ));
+            }
+            env.kill_background_process(rest.first().map(String::as_str), signal)?;
         }
-        "chmod" => {
-            if command.args.len() != 2 {
-                return Err(Error::command_error(
-                    "chmod",
-                    "Expected exactly 2 arguments",
+        "interrupt" => {
+            if args.len() > 1 {
+                return Err(Error::usage_error(
+                    "interrupt",
+                    "Expected at most 1 argument",
                 ));
             }
-            env.change_permissions(&command.args[0], &command.args[1])?;
+            env.kill_background_process(args.first().map(String::as_str), Some("INT"))?;
+        }
+        "chmod" => {
+            if args.len() != 2 {
+                return Err(Error::usage_error("chmod", "Expected exactly 2 arguments"));
+            }
+            env.change_permissions(&args[0], &args[1])?;
         }
         "symlink" => {
-            if command.args.len() != 2 {
-                return Err(Error::command_error(
+            if args.len() != 2 {
+                return Err(Error::usage_error(
                     "symlink",
                     "Expected exactly 2 arguments: target link_name",
                 ));
             }
-            env.create_symlink(&command.args[0], &command.args[1])?;
+            env.create_symlink(&args[0], &args[1])?;
         }
         "unquote" => {
-            if command.args.len() != 1 {
-                return Err(Error::command_error(
-                    "unquote",
-                    "Expected exactly 1 argument",
-                ));
+            if args.len() != 1 {
+                return Err(Error::usage_error("unquote", "Expected exactly 1 argument"));
             }
-            env.unquote_file(&command.args[0])?;
+            env.unquote_file(&args[0])?;
         }
         "grep" => {
-            if command.args.len() < 2 {
-                return Err(Error::command_error(
-                    "grep",
-                    "Expected at least 2 arguments",
-                ));
+            if args.len() < 2 {
+                return Err(Error::usage_error("grep", "Expected at least 2 arguments"));
             }
-            let pattern = &command.args[0];
-            let files = &command.args[1..];
+            let pattern = &args[0];
+            let files = &args[1..];
             env.grep_files(pattern, files)?;
         }
         _ => {
@@ -529,3 +995,66 @@ fn execute_command_inner(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30s"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1.5m"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("0"), Some(Duration::ZERO));
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration("10d"), None);
+        assert_eq!(parse_duration(""), None);
+    }
+
+    #[test]
+    fn test_expected_output_literal_round_trips() {
+        for output in [
+            "plain",
+            "two words",
+            "a.b*c (x) [y] ^z {1} a+b? x|y",
+            "$HOME and $$ and ${X}",
+            r#"back\slash "double" 'single'"#,
+            "tab\there\r",
+            "line one\nline two",
+            "&",
+            "&job&",
+            "",
+            "-",
+            "-count=2",
+        ] {
+            let literal = expected_output_literal(output, false);
+            let script = crate::parser::parse(&format!("stdout {}", literal)).unwrap();
+            let command = &script.commands[0];
+            assert!(!command.background, "{:?} became a marker", literal);
+
+            let expected =
+                crate::run::environment::expand_vars(&command.arg_templates[0], |_| None).unwrap();
+            assert!(!expected.starts_with("-count="), "{:?} is a flag", literal);
+            if looks_like_regex(&expected) {
+                let regex = regex::Regex::new(&format!("(?su){}", expected)).unwrap();
+                assert!(regex.is_match(output), "{:?} doesn't match", literal);
+                assert!(!regex.is_match(&format!("{}x", output)), "{:?}", literal);
+            } else {
+                assert_ne!(expected, "-", "{:?} reads as no output", literal);
+                assert_eq!(expected, output, "{:?} doesn't read back", literal);
+            }
+        }
+
+        assert_eq!(expected_output_literal("plain", false), "plain");
+        assert_eq!(expected_output_literal("two words", false), "\"two words\"");
+        assert_eq!(expected_output_literal("1.5", false), "'^1\\.5$'");
+        assert_eq!(expected_output_literal("$5", false), "'^\\$5$'");
+        assert_eq!(expected_output_literal(r"C:\new", false), r#""C:\\new""#);
+        assert_eq!(expected_output_literal("-", false), "'^-$'");
+        assert_eq!(expected_output_literal("-count=2", false), "'^-count=2$'");
+        // A file name reads as that file's contents unless anchored
+        assert_eq!(expected_output_literal("notes", true), "'^notes$'");
+    }
+}
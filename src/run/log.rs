@@ -0,0 +1,190 @@
+//! Go-compatible script execution log
+//!
+//! Each comment line in a script starts a new section, logged as the comment
+//! followed by the time the section took. Commands are echoed as `> command`
+//! with their output in `[stdout]`/`[stderr]`/`[exit status N]` blocks. Unless
+//! the log is verbose, a section's commands are dropped once it completes
+//! without failing, leaving only its header.
+
+use crate::run::process;
+use std::fmt::Write;
+use std::process::{ExitStatus, Output};
+use std::time::Instant;
+
+/// The log for a single script run
+pub(crate) struct ScriptLog {
+    text: String,
+    verbose: bool,
+    section: Section,
+}
+
+/// The section currently being logged
+struct Section {
+    /// Where to insert the elapsed time: the end of the header line
+    header_end: Option<usize>,
+    /// Where the section's commands start
+    body_start: usize,
+    started: Instant,
+    /// Whether the commands must be kept because one of them failed
+    failed: bool,
+}
+
+impl Section {
+    fn new(header_end: Option<usize>, body_start: usize) -> Self {
+        Section {
+            header_end,
+            body_start,
+            started: Instant::now(),
+            failed: false,
+        }
+    }
+}
+
+impl ScriptLog {
+    /// Create an empty log; a verbose log keeps every section's commands
+    pub(crate) fn new(verbose: bool) -> Self {
+        ScriptLog {
+            text: String::new(),
+            verbose,
+            section: Section::new(None, 0),
+        }
+    }
+
+    /// Start a new section headed by a comment line
+    pub(crate) fn section(&mut self, comment: &str) {
+        self.end_section();
+        self.text.push_str(comment);
+        let header_end = self.text.len();
+        self.text.push('\n');
+        self.section = Section::new(Some(header_end), self.text.len());
+    }
+
+    /// Echo a command line
+    pub(crate) fn command(&mut self, line: &str) {
+        let _ = writeln!(self.text, "> {}", line);
+    }
+
+    /// Log the output and exit status of a command
+    pub(crate) fn output(&mut self, output: &Output) {
+        self.streams(output);
+        if !output.status.success() {
+            let _ = writeln!(self.text, "[{}]", describe_status(&output.status));
+        }
+    }
+
+    /// Log the output of a background process that is still running
+    pub(crate) fn running_output(&mut self, output: &Output) {
+        self.streams(output);
+        self.text.push_str("[still running]\n");
+    }
+
+    /// Log a command's stdout and stderr, each under its own header
+    fn streams(&mut self, output: &Output) {
+        for (name, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if bytes.is_empty() {
+                continue;
+            }
+            let _ = writeln!(self.text, "[{}]", name);
+            self.text.push_str(&String::from_utf8_lossy(bytes));
+            if !self.text.ends_with('\n') {
+                self.text.push('\n');
+            }
+        }
+    }
+
+    /// Log a failure, keeping the current section's commands
+    pub(crate) fn failure(&mut self, location: &str, error: &dyn std::fmt::Display) {
+        let _ = writeln!(self.text, "FAIL: {}: {}", location, error);
+        self.section.failed = true;
+    }
+
+    /// Finish the last section and return the log text
+    pub(crate) fn finish(mut self) -> String {
+        self.end_section();
+        self.text
+    }
+
+    fn end_section(&mut self) {
+        let section = std::mem::replace(&mut self.section, Section::new(None, self.text.len()));
+        if !self.verbose && !section.failed {
+            self.text.truncate(section.body_start);
+        }
+        if let Some(header_end) = section.header_end {
+            let elapsed = format!(" ({:.3}s)", section.started.elapsed().as_secs_f64());
+            self.text.insert_str(header_end, &elapsed);
+        }
+    }
+}
+
+/// Describe how a process exited, as Go's testscript does
+fn describe_status(status: &ExitStatus) -> String {
+    match status.code() {
+        Some(code) => format!("exit status {}", code),
+        None => process::describe_exit(status),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn output(stdout: &str, code: i32) -> Output {
+        use std::os::unix::process::ExitStatusExt;
+        Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: stdout.as_bytes().to_vec(),
+            stderr: Vec::new(),
+        }
+    }
+
+    /// Replace the timings, which vary from run to run
+    fn normalize(log: &str) -> String {
+        let re = regex::Regex::new(r"\(\d+\.\d{3}s\)").unwrap();
+        re.replace_all(log, "(0.000s)").into_owned()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_quiet_log_keeps_only_failed_sections() {
+        let mut log = ScriptLog::new(false);
+        log.section("# comment 1");
+        log.command("printargs section1");
+        log.output(&output("section1\n", 0));
+        log.section("# comment 2");
+        log.command("status 1");
+        log.output(&output("", 1));
+        log.failure("script.txt:4", &"unexpected command failure");
+        log.section("# comment 3");
+        log.command("printargs section3");
+
+        assert_eq!(
+            normalize(&log.finish()),
+            "# comment 1 (0.000s)\n\
+             # comment 2 (0.000s)\n\
+             > status 1\n\
+             [exit status 1]\n\
+             FAIL: script.txt:4: unexpected command failure\n\
+             # comment 3 (0.000s)\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_verbose_log_keeps_every_section() {
+        let mut log = ScriptLog::new(true);
+        log.command("echo before");
+        log.section("# comment 1");
+        log.command("printargs section1");
+        log.output(&output("section1", 0));
+
+        assert_eq!(
+            normalize(&log.finish()),
+            "> echo before\n\
+             # comment 1 (0.000s)\n\
+             > printargs section1\n\
+             [stdout]\n\
+             section1\n"
+        );
+    }
+}
@@ -3,22 +3,28 @@
 pub mod commands;
 pub mod environment;
 pub mod execution;
+mod log;
 pub mod params;
+mod process;
 
 // Re-export public types
 pub use environment::TestEnvironment;
-pub use params::{CommandFn, RunParams, SetupFn};
+pub use execution::ScriptOutcome;
+pub use params::{CommandFn, ConditionFn, RunParams, SetupFn};
 
 use crate::error::Result;
 use std::path::Path;
 
 /// Run a single test script
-pub fn run_test(script_path: &Path) -> Result<()> {
+pub fn run_test(script_path: &Path) -> Result<ScriptOutcome> {
     let params = RunParams::new();
     run_script(script_path, &params)
 }
 
 /// Run a single script with the given parameters
-pub fn run_script(script_path: &Path, params: &RunParams) -> Result<()> {
+///
+/// Returns how the script finished; a script that ends with `skip` is
+/// reported as [`ScriptOutcome::Skipped`] rather than as an error.
+pub fn run_script(script_path: &Path, params: &RunParams) -> Result<ScriptOutcome> {
     execution::run_script_impl(script_path, params)
 }
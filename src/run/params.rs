@@ -3,12 +3,58 @@
 use crate::error::Result;
 use crate::run::environment::TestEnvironment;
 use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
 
 /// Type alias for a custom command function
 pub type CommandFn = fn(&mut TestEnvironment, &[String]) -> Result<()>;
 
 /// Type alias for a setup function
-pub type SetupFn = Box<dyn Fn(&mut TestEnvironment) -> Result<()>>;
+///
+/// Setup functions are shared between worker threads when scripts run in
+/// parallel, so they must be `Send + Sync`.
+pub type SetupFn = Box<dyn Fn(&mut TestEnvironment) -> Result<()> + Send + Sync>;
+
+/// Type alias for a condition provider function
+///
+/// A provider is called with the argument after its prefix, such as `serde`
+/// for `[feature:serde]`, and the environment of the running script. Like
+/// setup functions, providers must be `Send + Sync`.
+pub type ConditionFn = Box<dyn Fn(&str, &TestEnvironment) -> Result<bool> + Send + Sync>;
+
+/// A family of conditions sharing a prefix, such as `exec:program`
+pub struct ConditionProvider {
+    /// The function deciding whether a condition holds
+    pub func: ConditionFn,
+    /// Whether the result depends only on the argument, so the provider is
+    /// called at most once per argument in a run
+    pub pure: bool,
+    /// Results of a pure provider by argument, shared by every script in
+    /// the run
+    cache: Mutex<HashMap<String, bool>>,
+}
+
+impl ConditionProvider {
+    /// Decide whether the condition with argument `arg` holds
+    ///
+    /// A pure provider's result is reused for the rest of the run. The cache
+    /// stays locked while the provider runs, so scripts running in parallel
+    /// wait for the first one to ask instead of calling it again. A provider
+    /// that panics fails only the script that called it.
+    pub fn check(&self, arg: &str, env: &TestEnvironment) -> Result<bool> {
+        if !self.pure {
+            return (self.func)(arg, env);
+        }
+        // A panic leaves nothing half-written, since results are only
+        // inserted once the provider returns
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(&met) = cache.get(arg) {
+            return Ok(met);
+        }
+        let met = (self.func)(arg, env)?;
+        cache.insert(arg.to_string(), met);
+        Ok(met)
+    }
+}
 
 /// Configuration parameters for running tests
 pub struct RunParams {
@@ -18,6 +64,8 @@ pub struct RunParams {
     pub setup: Option<SetupFn>,
     /// Conditions that can be checked in scripts
     pub conditions: HashMap<String, bool>,
+    /// Providers for conditions with an argument, indexed by prefix
+    pub condition_providers: HashMap<String, ConditionProvider>,
     /// Whether to update test scripts with actual output
     pub update_scripts: bool,
     /// Whether to preserve working directories when tests fail
@@ -26,6 +74,20 @@ pub struct RunParams {
     pub workdir_root: Option<std::path::PathBuf>,
     /// Specific files to run (if None, discover all .txt files)
     pub files: Option<Vec<String>>,
+    /// Whether to run scripts without inheriting the host environment
+    pub hermetic: bool,
+    /// Host environment variables passed through in hermetic mode
+    pub passthrough_env: Vec<String>,
+    /// Whether to keep executing a script after a command fails
+    pub continue_on_failure: bool,
+    /// Number of scripts to run concurrently (if None, use the available CPUs)
+    pub parallel: Option<usize>,
+    /// Maximum time a single command may run before it is killed
+    pub command_timeout: Option<std::time::Duration>,
+    /// Maximum time a whole script may run before it is killed
+    pub script_timeout: Option<std::time::Duration>,
+    /// Whether to print the full execution log of every script
+    pub verbose: bool,
 }
 
 impl RunParams {
@@ -53,15 +115,31 @@ impl RunParams {
             .map(|v| v == "1" || v.to_lowercase() == "true")
             .unwrap_or(false);
 
+        // Check TESTSCRIPT_VERBOSE environment variable
+        let verbose = std::env::var("TESTSCRIPT_VERBOSE")
+            .map(|v| v == "1" || v.to_lowercase() == "true")
+            .unwrap_or(false);
+
         RunParams {
             commands: HashMap::new(),
             setup: None,
             conditions,
+            condition_providers: HashMap::new(),
             update_scripts,
             preserve_work_on_failure: false,
             workdir_root: None,
             files: None,
+            hermetic: false,
+            passthrough_env: Vec::new(),
+            continue_on_failure: false,
+            parallel: None,
+            command_timeout: None,
+            script_timeout: None,
+            verbose,
         }
+        // Add the program and environment variable conditions
+        .pure_condition_fn("exec", |program, _env| Ok(Self::program_exists(program)))
+        .condition_fn("env", |var, env| Ok(env.lookup_env_var(var).is_some()))
     }
 
     /// Add a custom command
@@ -73,7 +151,7 @@ impl RunParams {
     /// Set a setup function to run before each script
     pub fn setup<F>(mut self, func: F) -> Self
     where
-        F: Fn(&mut TestEnvironment) -> Result<()> + 'static,
+        F: Fn(&mut TestEnvironment) -> Result<()> + Send + Sync + 'static,
     {
         self.setup = Some(Box::new(func));
         self
@@ -85,6 +163,58 @@ impl RunParams {
         self
     }
 
+    /// Add a provider for conditions starting with `prefix`
+    ///
+    /// The provider is called every time such a condition is evaluated.
+    pub fn condition_fn<F>(self, prefix: &str, func: F) -> Self
+    where
+        F: Fn(&str, &TestEnvironment) -> Result<bool> + Send + Sync + 'static,
+    {
+        self.add_condition_provider(prefix, Box::new(func), false)
+    }
+
+    /// Add a provider for conditions starting with `prefix` whose result
+    /// depends only on its argument, so the run caches it
+    pub fn pure_condition_fn<F>(self, prefix: &str, func: F) -> Self
+    where
+        F: Fn(&str, &TestEnvironment) -> Result<bool> + Send + Sync + 'static,
+    {
+        self.add_condition_provider(prefix, Box::new(func), true)
+    }
+
+    fn add_condition_provider(mut self, prefix: &str, func: ConditionFn, pure: bool) -> Self {
+        let provider = ConditionProvider {
+            func,
+            pure,
+            cache: Mutex::new(HashMap::new()),
+        };
+        self.condition_providers
+            .insert(prefix.to_string(), provider);
+        self
+    }
+
+    /// Find the provider for a condition name, with the argument to pass it
+    ///
+    /// A name matches a prefix when the rest of it is empty or starts with a
+    /// character that can't continue a word, so `feature` provides
+    /// `[feature:serde]` and `[feature>=2]` but not `[features]`. A `:`
+    /// after the prefix is not part of the argument. The longest matching
+    /// prefix wins.
+    pub fn condition_provider<'a>(&self, name: &'a str) -> Option<(&ConditionProvider, &'a str)> {
+        self.condition_providers
+            .iter()
+            .filter_map(|(prefix, provider)| {
+                let rest = name.strip_prefix(prefix.as_str())?;
+                let continues_word = rest
+                    .chars()
+                    .next()
+                    .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '-');
+                (!continues_word).then_some((prefix.len(), provider, rest))
+            })
+            .max_by_key(|(len, _, _)| *len)
+            .map(|(_, provider, rest)| (provider, rest.strip_prefix(':').unwrap_or(rest)))
+    }
+
     /// Set whether to update scripts with actual output
     pub fn update_scripts(mut self, update: bool) -> Self {
         self.update_scripts = update;
@@ -119,10 +249,69 @@ impl RunParams {
         self
     }
 
+    /// Set whether scripts run in a hermetic environment
+    ///
+    /// When enabled, commands do not inherit the host environment. Instead each
+    /// script starts from a fixed baseline (see [`TestEnvironment::make_hermetic`]).
+    pub fn hermetic(mut self, hermetic: bool) -> Self {
+        self.hermetic = hermetic;
+        self
+    }
+
+    /// Add host environment variables to pass through in hermetic mode
+    pub fn passthrough_env<I, S>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.passthrough_env
+            .extend(vars.into_iter().map(|s| s.into()));
+        self
+    }
+
+    /// Set whether to keep executing a script after a command fails
+    pub fn continue_on_failure(mut self, continue_on_failure: bool) -> Self {
+        self.continue_on_failure = continue_on_failure;
+        self
+    }
+
+    /// Set the number of scripts to run concurrently
+    ///
+    /// A value of 0 is treated as 1.
+    pub fn parallel(mut self, workers: usize) -> Self {
+        self.parallel = Some(workers.max(1));
+        self
+    }
+
+    /// Set the maximum time a single command may run
+    pub fn command_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.command_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the maximum time a whole script may run
+    pub fn script_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.script_timeout = Some(timeout);
+        self
+    }
+
+    /// Enable or disable verbose logging
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.verbose = verbose;
+        self
+    }
+
+    /// Number of worker threads to run scripts on
+    pub fn worker_count(&self) -> usize {
+        self.parallel.unwrap_or_else(|| {
+            std::thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+    }
+
     /// Check if a program exists in PATH (cross-platform)
     pub fn program_exists(program: &str) -> bool {
-        // TODO: Consider caching results for performance if needed
-
         // Use different commands based on platform
         #[cfg(windows)]
         let check_cmd = "where";
@@ -193,15 +382,6 @@ impl RunParams {
         }
         false
     }
-
-    /// Check environment variable condition
-    pub fn check_env_condition(condition: &str) -> bool {
-        if let Some(env_var) = condition.strip_prefix("env:") {
-            std::env::var(env_var).is_ok()
-        } else {
-            false
-        }
-    }
 }
 
 impl Default for RunParams {
@@ -209,3 +389,64 @@ impl Default for RunParams {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_params_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<RunParams>();
+    }
+
+    #[test]
+    fn test_worker_count() {
+        assert_eq!(RunParams::new().parallel(4).worker_count(), 4);
+        assert_eq!(RunParams::new().parallel(0).worker_count(), 1);
+        assert!(RunParams::new().worker_count() >= 1);
+    }
+
+    #[test]
+    fn test_condition_provider_matching() {
+        let params = RunParams::new()
+            .condition_fn("kernel", |_, _| Ok(true))
+            .condition_fn("kernel-rt", |_, _| Ok(false));
+        let argument = |name| params.condition_provider(name).map(|(_, arg)| arg);
+
+        assert_eq!(argument("kernel>=5.10"), Some(">=5.10"));
+        assert_eq!(argument("kernel:6"), Some("6"));
+        assert_eq!(argument("kernel"), Some(""));
+        assert_eq!(argument("kernels"), None);
+        assert_eq!(argument("exec:git"), Some("git"));
+        assert_eq!(argument("executable"), None);
+
+        let env = TestEnvironment::new().unwrap();
+        let (provider, arg) = params.condition_provider("kernel-rt:6").unwrap();
+        assert_eq!(arg, "6");
+        assert!(!provider.check(arg, &env).unwrap());
+        assert!(params
+            .condition_provider("env:HOME")
+            .is_some_and(|(p, _)| !p.pure));
+        assert!(params
+            .condition_provider("exec:git")
+            .is_some_and(|(p, _)| p.pure));
+    }
+
+    #[test]
+    fn test_pure_provider_survives_a_panic() {
+        let params = RunParams::new().pure_condition_fn("flaky", |arg, _| {
+            assert_ne!(arg, "panic", "provider failed");
+            Ok(arg == "yes")
+        });
+        let env = TestEnvironment::new().unwrap();
+        let provider = &params.condition_providers["flaky"];
+
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            provider.check("panic", &env)
+        }));
+        assert!(panicked.is_err());
+        assert!(provider.check("yes", &env).unwrap());
+        assert!(!provider.check("no", &env).unwrap());
+    }
+}
@@ -0,0 +1,394 @@
+//! Child process helpers: output capture, deadlines and process groups
+
+use crate::error::{Error, Result};
+use std::io::Read;
+use std::process::{Child, Command as StdCommand, ExitStatus, Output};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for output readers after a process has been killed
+pub(crate) const KILLED_READER_GRACE: Duration = Duration::from_millis(500);
+
+/// Continuously drains a child's output pipe into a shared buffer
+#[derive(Debug)]
+pub(crate) struct OutputCapture {
+    buffer: Arc<Mutex<Vec<u8>>>,
+    done: Receiver<()>,
+}
+
+impl OutputCapture {
+    /// Start draining `pipe` on a background thread
+    pub(crate) fn spawn<R: Read + Send + 'static>(mut pipe: R) -> Self {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let (done_tx, done) = mpsc::channel();
+        let thread_buffer = Arc::clone(&buffer);
+
+        thread::spawn(move || {
+            let mut chunk = [0u8; 8192];
+            loop {
+                match pipe.read(&mut chunk) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => thread_buffer.lock().unwrap().extend_from_slice(&chunk[..n]),
+                }
+            }
+            let _ = done_tx.send(());
+        });
+
+        OutputCapture { buffer, done }
+    }
+
+    /// The output captured so far
+    pub(crate) fn snapshot(&self) -> Vec<u8> {
+        self.buffer.lock().unwrap().clone()
+    }
+
+    /// Wait up to `grace` for the pipe to close, so that the snapshot holds
+    /// everything written to it
+    ///
+    /// Returns whether the pipe closed in time.
+    pub(crate) fn wait_for_close(&self, grace: Duration) -> bool {
+        !matches!(
+            self.done.recv_timeout(grace),
+            Err(RecvTimeoutError::Timeout)
+        )
+    }
+
+    /// Wait for the pipe to close and return everything captured
+    pub(crate) fn finish(self) -> Vec<u8> {
+        let _ = self.done.recv();
+        self.snapshot()
+    }
+
+    /// Like `finish`, but give up waiting after `grace`
+    ///
+    /// Used after killing a process, when a descendant that escaped the kill
+    /// could otherwise hold the pipe open forever.
+    pub(crate) fn finish_within(self, grace: Duration) -> Vec<u8> {
+        let _ = self.done.recv_timeout(grace);
+        self.snapshot()
+    }
+}
+
+/// Start the command in its own process group so it can be killed as a whole
+pub(crate) fn set_new_process_group(command: &mut StdCommand) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = command;
+}
+
+/// Forcibly kill a child and, on Unix, every process in its group
+pub(crate) fn kill_process_group(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        // The child leads its own group, so its pid is the group id
+        // SAFETY: kill has no memory safety preconditions
+        unsafe {
+            libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+        }
+    }
+    let _ = child.kill();
+}
+
+/// Send a signal, given by name (`INT`, `SIGTERM`) or number, to a child
+///
+/// Returns `None` if the signal is unknown or, off Unix, anything other than
+/// `KILL`, which is the only signal that can be sent there.
+pub(crate) fn send_signal(child: &mut Child, signal: &str) -> Option<std::io::Result<()>> {
+    #[cfg(unix)]
+    {
+        let number = parse_signal(signal)?;
+        // SAFETY: kill has no memory safety preconditions
+        let result = unsafe { libc::kill(child.id() as libc::pid_t, number) };
+        Some(if result == 0 {
+            Ok(())
+        } else {
+            Err(std::io::Error::last_os_error())
+        })
+    }
+    #[cfg(not(unix))]
+    match signal {
+        "KILL" | "SIGKILL" | "9" => Some(child.kill()),
+        _ => None,
+    }
+}
+
+/// Wait for a child whose stdout and stderr are piped, enforcing a deadline
+///
+/// The deadline also covers reading the output after the child exits, which
+/// lasts as long as any process it started keeps the pipes open. If the
+/// deadline passes first, the child's process group is killed and an
+/// [`Error::Timeout`] carrying the output captured so far is returned.
+pub(crate) fn wait_with_deadline(
+    mut child: Child,
+    stdout: OutputCapture,
+    stderr: OutputCapture,
+    command: &str,
+    deadline: Option<(Instant, Duration)>,
+) -> Result<Output> {
+    let Some((deadline, timeout)) = deadline else {
+        let status = child.wait()?;
+        return Ok(Output {
+            status,
+            stdout: stdout.finish(),
+            stderr: stderr.finish(),
+        });
+    };
+
+    let mut poll_interval = Duration::from_millis(1);
+    loop {
+        if let Some(status) = child.try_wait()? {
+            let left = || deadline.saturating_duration_since(Instant::now());
+            if stdout.wait_for_close(left()) && stderr.wait_for_close(left()) {
+                return Ok(Output {
+                    status,
+                    stdout: stdout.snapshot(),
+                    stderr: stderr.snapshot(),
+                });
+            }
+            return Err(timed_out(child, stdout, stderr, command, timeout));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(timed_out(child, stdout, stderr, command, timeout));
+        }
+        thread::sleep(poll_interval.min(deadline - now));
+        poll_interval = (poll_interval * 2).min(Duration::from_millis(20));
+    }
+}
+
+/// Kill a child's process group after its deadline passed, and build the
+/// timeout error from the output captured so far
+fn timed_out(
+    mut child: Child,
+    stdout: OutputCapture,
+    stderr: OutputCapture,
+    command: &str,
+    timeout: Duration,
+) -> Error {
+    kill_process_group(&mut child);
+    let _ = child.wait();
+    Error::Timeout {
+        command: command.to_string(),
+        timeout,
+        stdout: String::from_utf8_lossy(&stdout.finish_within(KILLED_READER_GRACE)).to_string(),
+        stderr: String::from_utf8_lossy(&stderr.finish_within(KILLED_READER_GRACE)).to_string(),
+    }
+}
+
+/// Describe how a process ended, e.g. "exit code 2" or "killed by SIGSEGV"
+pub(crate) fn describe_exit(status: &ExitStatus) -> String {
+    if let Some(code) = status.code() {
+        return format!("exit code {}", code);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return match signal_name(signal) {
+                Some(name) => format!("killed by {}", name),
+                None => format!("killed by signal {}", signal),
+            };
+        }
+    }
+    "killed".to_string()
+}
+
+/// The status given to output taken from a process that is still running
+pub(crate) fn running_status() -> ExitStatus {
+    #[cfg(unix)]
+    use std::os::unix::process::ExitStatusExt;
+    #[cfg(windows)]
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(0)
+}
+
+/// The exit code of a process, or 128 plus the signal number if it was
+/// killed by a signal, as shells report it
+pub(crate) fn exit_code(status: &ExitStatus) -> i32 {
+    if let Some(code) = status.code() {
+        return code;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    -1
+}
+
+/// Every signal with a conventional name, apart from the real-time ones
+#[cfg(unix)]
+const NAMED_SIGNALS: &[(i32, &str)] = &[
+    (libc::SIGHUP, "SIGHUP"),
+    (libc::SIGINT, "SIGINT"),
+    (libc::SIGQUIT, "SIGQUIT"),
+    (libc::SIGILL, "SIGILL"),
+    (libc::SIGTRAP, "SIGTRAP"),
+    (libc::SIGABRT, "SIGABRT"),
+    (libc::SIGBUS, "SIGBUS"),
+    (libc::SIGFPE, "SIGFPE"),
+    (libc::SIGKILL, "SIGKILL"),
+    (libc::SIGUSR1, "SIGUSR1"),
+    (libc::SIGSEGV, "SIGSEGV"),
+    (libc::SIGUSR2, "SIGUSR2"),
+    (libc::SIGPIPE, "SIGPIPE"),
+    (libc::SIGALRM, "SIGALRM"),
+    (libc::SIGTERM, "SIGTERM"),
+    (libc::SIGCHLD, "SIGCHLD"),
+    (libc::SIGCONT, "SIGCONT"),
+    (libc::SIGSTOP, "SIGSTOP"),
+    (libc::SIGTSTP, "SIGTSTP"),
+    (libc::SIGTTIN, "SIGTTIN"),
+    (libc::SIGTTOU, "SIGTTOU"),
+    (libc::SIGURG, "SIGURG"),
+    (libc::SIGXCPU, "SIGXCPU"),
+    (libc::SIGXFSZ, "SIGXFSZ"),
+    (libc::SIGVTALRM, "SIGVTALRM"),
+    (libc::SIGPROF, "SIGPROF"),
+    (libc::SIGWINCH, "SIGWINCH"),
+    (libc::SIGIO, "SIGIO"),
+    (libc::SIGSYS, "SIGSYS"),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    (libc::SIGPWR, "SIGPWR"),
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    (libc::SIGEMT, "SIGEMT"),
+    #[cfg(any(
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "netbsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    (libc::SIGINFO, "SIGINFO"),
+];
+
+/// The range of real-time signals, where the platform has them
+#[cfg(unix)]
+fn realtime_signals() -> Option<std::ops::RangeInclusive<i32>> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    return Some(libc::SIGRTMIN()..=libc::SIGRTMAX());
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    None
+}
+
+/// The highest signal number that can be sent
+#[cfg(unix)]
+fn max_signal() -> i32 {
+    realtime_signals()
+        .map(|signals| *signals.end())
+        .into_iter()
+        .chain(NAMED_SIGNALS.iter().map(|&(number, _)| number))
+        .max()
+        .unwrap_or(31)
+}
+
+/// The conventional name of a signal number
+///
+/// Real-time signals are named as `kill -l` lists them on Linux, counting up
+/// from `SIGRTMIN` in the lower half of the range and down from `SIGRTMAX`
+/// in the upper half.
+#[cfg(unix)]
+pub(crate) fn signal_name(signal: i32) -> Option<String> {
+    if let Some(&(_, name)) = NAMED_SIGNALS.iter().find(|&&(number, _)| number == signal) {
+        return Some(name.to_string());
+    }
+    let realtime = realtime_signals().filter(|signals| signals.contains(&signal))?;
+    let (min, max) = (*realtime.start(), *realtime.end());
+    Some(match signal - min {
+        0 => "SIGRTMIN".to_string(),
+        offset if offset <= (max - min) / 2 => format!("SIGRTMIN+{}", offset),
+        _ if signal == max => "SIGRTMAX".to_string(),
+        _ => format!("SIGRTMAX-{}", max - signal),
+    })
+}
+
+/// The number of a signal given by name, with or without the `SIG` prefix,
+/// or by number
+///
+/// Numbers outside the platform's signal range are rejected.
+#[cfg(unix)]
+pub(crate) fn parse_signal(signal: &str) -> Option<i32> {
+    if let Ok(number) = signal.parse::<i32>() {
+        return Some(number).filter(|&n| (1..=max_signal()).contains(&n));
+    }
+    let name = format!("SIG{}", signal.strip_prefix("SIG").unwrap_or(signal));
+    (1..=max_signal()).find(|&number| signal_name(number).as_deref() == Some(name.as_str()))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    #[test]
+    fn test_describe_exit() {
+        assert_eq!(describe_exit(&ExitStatus::from_raw(2 << 8)), "exit code 2");
+        assert_eq!(
+            describe_exit(&ExitStatus::from_raw(libc::SIGSEGV)),
+            "killed by SIGSEGV"
+        );
+        assert_eq!(
+            describe_exit(&ExitStatus::from_raw(100)),
+            "killed by signal 100"
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        assert_eq!(exit_code(&ExitStatus::from_raw(0)), 0);
+        assert_eq!(exit_code(&ExitStatus::from_raw(3 << 8)), 3);
+        assert_eq!(exit_code(&ExitStatus::from_raw(libc::SIGKILL)), 128 + 9);
+    }
+
+    #[test]
+    fn test_parse_signal() {
+        assert_eq!(parse_signal("INT"), Some(libc::SIGINT));
+        assert_eq!(parse_signal("SIGTERM"), Some(libc::SIGTERM));
+        assert_eq!(parse_signal("KILL"), Some(libc::SIGKILL));
+        assert_eq!(parse_signal("10"), Some(10));
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("BOGUS"), None);
+        assert_eq!(parse_signal("int"), None);
+        assert_eq!(parse_signal("STOP"), Some(libc::SIGSTOP));
+        assert_eq!(parse_signal("SIGCONT"), Some(libc::SIGCONT));
+        assert_eq!(parse_signal("WINCH"), Some(libc::SIGWINCH));
+        assert_eq!(parse_signal("999"), None);
+        assert_eq!(parse_signal("-1"), None);
+        for (number, name) in NAMED_SIGNALS {
+            assert_eq!(parse_signal(name), Some(*number));
+            assert_eq!(parse_signal(&name[3..]), Some(*number));
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_realtime_signals() {
+        let (min, max) = (libc::SIGRTMIN(), libc::SIGRTMAX());
+        assert_eq!(signal_name(min).as_deref(), Some("SIGRTMIN"));
+        assert_eq!(signal_name(min + 1).as_deref(), Some("SIGRTMIN+1"));
+        assert_eq!(signal_name(max).as_deref(), Some("SIGRTMAX"));
+        assert_eq!(signal_name(max - 1).as_deref(), Some("SIGRTMAX-1"));
+        for number in min..=max {
+            assert_eq!(parse_signal(&signal_name(number).unwrap()), Some(number));
+        }
+        assert_eq!(parse_signal(&max.to_string()), Some(max));
+        assert_eq!(parse_signal(&(max + 1).to_string()), None);
+    }
+}
@@ -2,7 +2,7 @@
 
 use std::fs;
 use tempfile::TempDir;
-use testscript_rs::{run::RunParams, testscript};
+use testscript_rs::{run::RunParams, testscript, TestEnvironment};
 
 #[test]
 fn test_env_conditions() {
@@ -36,6 +36,29 @@ stdout "negated env condition works"
     std::env::remove_var("TEST_CONDITION");
 }
 
+#[test]
+fn test_env_conditions_follow_the_script_environment() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    std::env::set_var("HOST_ONLY_CONDITION", "true");
+
+    // Each check fails the script if its condition holds
+    let test_content = r#"[env:SCRIPT_VAR] exists not-set-yet
+env SCRIPT_VAR=set
+[!env:SCRIPT_VAR] exists set-by-env
+[env:HOST_ONLY_CONDITION] exists hidden-in-hermetic-mode
+"#;
+    fs::write(testdata_dir.join("env_script_test.txt"), test_content).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .hermetic(true)
+        .execute();
+    std::env::remove_var("HOST_ONLY_CONDITION");
+    assert!(result.is_ok(), "Env condition test failed: {:?}", result);
+}
+
 #[test]
 fn test_network_condition_builtin() {
     let temp_dir = TempDir::new().unwrap();
@@ -90,14 +113,14 @@ stdout "rare program not found"
 
 #[test]
 fn test_runparams_condition_helpers() {
-    // Test the helper functions directly
-    std::env::set_var("HELPER_TEST", "value");
-
-    assert!(RunParams::check_env_condition("env:HELPER_TEST"));
-    assert!(!RunParams::check_env_condition("env:NONEXISTENT"));
-    assert!(!RunParams::check_env_condition("not_env_condition"));
-
-    std::env::remove_var("HELPER_TEST");
+    // Test the env provider directly against a script environment
+    let params = RunParams::new();
+    let provider = &params.condition_providers["env"];
+    let mut env = TestEnvironment::new().unwrap();
+    env.set_env_var("HELPER_TEST", "value");
+
+    assert!(provider.check("HELPER_TEST", &env).unwrap());
+    assert!(!provider.check("NONEXISTENT", &env).unwrap());
 }
 
 #[test]
@@ -126,13 +149,26 @@ fn test_combined_conditions() {
     std::env::set_var("COMBINED_TEST", "true");
 
     let test_content = r#"# Test combining different condition types
-# Use separate lines since parser doesn't support multiple conditions per line
 [unix] exec echo "unix detected"
 [windows] exec echo "windows detected"
 
 # This should work on any platform with the env var
 [env:COMBINED_TEST] exec echo "env var is set"
 stdout "env var is set"
+
+# Every bracket must hold
+[env:COMBINED_TEST] [!env:NONEXISTENT_VAR] exec echo "both hold"
+stdout "both hold"
+[env:COMBINED_TEST] [env:NONEXISTENT_VAR] exec echo "skipped"
+stdout "both hold"
+
+# Operators combine conditions inside one bracket
+[unix || windows] exec echo "some platform"
+stdout "some platform"
+[env:NONEXISTENT_VAR && exec:echo] exec echo "skipped"
+stdout "some platform"
+[!(env:NONEXISTENT_VAR || !env:COMBINED_TEST) && (debug || release)] exec echo "grouped"
+stdout "grouped"
 "#;
 
     fs::write(testdata_dir.join("combined_test.txt"), test_content).unwrap();
@@ -178,3 +214,160 @@ stdout "Test completed"
         result
     );
 }
+
+#[cfg(unix)]
+#[test]
+fn test_unknown_condition_fails_before_running() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    // The unknown name is behind a short circuit, so it would never be
+    // evaluated, and the first command would leave a marker file behind
+    let marker = temp_dir.path().join("ran");
+    let test_content = format!(
+        "exec sh -c 'touch {}'\n[unix || no-such-condition] exec echo hello\n",
+        marker.display()
+    );
+    fs::write(testdata_dir.join("unknown_test.txt"), test_content).unwrap();
+
+    let error = testscript::run(testdata_dir.to_string_lossy())
+        .execute()
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("Unknown condition: no-such-condition"),
+        "Error: {}",
+        error
+    );
+    assert!(error.contains("line 2"), "Error: {}", error);
+    assert!(!marker.exists(), "No command should have run");
+}
+
+#[test]
+fn test_condition_fn_receives_argument_and_environment() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    let test_content = r#"env MODE=fast
+[mode:fast] exec echo "fast mode"
+stdout "fast mode"
+[mode:slow] exec echo "slow mode"
+stdout "fast mode"
+
+env MODE=slow
+[mode:slow && version>=2] exec echo "slow mode"
+stdout "slow mode"
+[version>=3] exec echo "version 3"
+stdout "slow mode"
+"#;
+    fs::write(testdata_dir.join("provider_test.txt"), test_content).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .condition_fn("mode", |mode, env| {
+            Ok(env.env_vars.get("MODE").map(String::as_str) == Some(mode))
+        })
+        .condition_fn("version", |requirement, _env| {
+            let minimum: u32 = requirement.trim_start_matches(">=").parse().unwrap();
+            Ok(minimum <= 2)
+        })
+        .execute();
+    assert!(
+        result.is_ok(),
+        "Condition provider test failed: {:?}",
+        result
+    );
+}
+
+#[test]
+fn test_pure_condition_fn_is_cached_per_run() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    let test_content = r#"[feature:serde] exec echo one
+[feature:serde] exec echo two
+[!feature:serde || feature:tokio] exec echo three
+[feature:tokio] exec echo four
+"#;
+    fs::write(testdata_dir.join("a.txt"), test_content).unwrap();
+    fs::write(testdata_dir.join("b.txt"), test_content).unwrap();
+
+    let pure_calls = Arc::new(AtomicUsize::new(0));
+    let pure = pure_calls.clone();
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .pure_condition_fn("feature", move |feature, _env| {
+            pure.fetch_add(1, Ordering::SeqCst);
+            Ok(feature == "serde")
+        })
+        .execute();
+    assert!(result.is_ok(), "Pure condition test failed: {:?}", result);
+
+    // The run asks once for serde and once for tokio, across both scripts
+    assert_eq!(pure_calls.load(Ordering::SeqCst), 2);
+
+    fs::write(
+        testdata_dir.join("a.txt"),
+        "[checked] exec echo one\n[checked] exec echo two\n",
+    )
+    .unwrap();
+    fs::remove_file(testdata_dir.join("b.txt")).unwrap();
+
+    // Other providers are asked every time
+    let impure_calls = Arc::new(AtomicUsize::new(0));
+    let impure = impure_calls.clone();
+    testscript::run(testdata_dir.to_string_lossy())
+        .condition_fn("checked", move |_, _env| {
+            impure.fetch_add(1, Ordering::SeqCst);
+            Ok(true)
+        })
+        .execute()
+        .unwrap();
+    assert_eq!(impure_calls.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_condition_fn_errors_fail_the_script() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    fs::write(
+        testdata_dir.join("error_test.txt"),
+        "[port-free:http] exec echo hello\n[unregistered:x] exec echo hello\n",
+    )
+    .unwrap();
+
+    // Unregistered prefixes are unknown before anything runs
+    let error = testscript::run(testdata_dir.to_string_lossy())
+        .condition_fn("port-free", |_, _env| Ok(true))
+        .execute()
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.contains("Unknown condition: unregistered:x"),
+        "Error: {}",
+        error
+    );
+
+    fs::write(
+        testdata_dir.join("error_test.txt"),
+        "[port-free:http] exec echo hello\n",
+    )
+    .unwrap();
+    let error = testscript::run(testdata_dir.to_string_lossy())
+        .condition_fn("port-free", |port, _env| {
+            Err(testscript_rs::Error::Generic(format!(
+                "Invalid port: {}",
+                port
+            )))
+        })
+        .execute()
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("Invalid port: http"), "Error: {}", error);
+}
@@ -0,0 +1,335 @@
+//! Tests for background processes
+
+mod common;
+
+use common::{assert_contains, assert_fails_with, assert_lacks, assert_passes, run_script};
+use std::path::Path;
+use testscript_rs::run_test;
+
+#[cfg(unix)]
+#[test]
+fn test_go_wait_script() {
+    assert_passes(run_test(Path::new("testdata/wait.txt")));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_named_background_processes() {
+    assert_passes(run_script(
+        r#"exec sh -c 'sleep 0.2; echo slow' &slow&
+exec echo fast &fast&
+wait fast
+stdout '^fast$'
+wait slow
+stdout '^slow$'
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_unnamed_processes_get_unique_names() {
+    assert_passes(run_script(
+        r#"exec echo first &
+exec echo second &
+exec echo third &
+wait echo-3
+stdout '^third$'
+wait echo
+stdout '^first$'
+wait echo-2
+stdout '^second$'
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_duplicate_background_name_is_an_error() {
+    assert_fails_with(
+        run_script(
+            r#"exec sleep 5 &server&
+exec sleep 5 &server&
+"#,
+        ),
+        "A background process named 'server' is already running",
+    );
+
+    // The name is free again once the process has been waited for
+    assert_passes(run_script(
+        r#"exec echo one &job&
+wait job
+exec echo two &job&
+wait job
+stdout '^two$'
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_wait_all_concatenates_in_start_order() {
+    assert_passes(run_script(
+        r#"exec sh -c 'sleep 0.2; echo a' &
+exec echo b &
+exec sh -c 'echo c >&2' &
+wait
+stdout '^a\nb$'
+stderr '^c$'
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_remaining_processes_are_killed_at_end() {
+    let start = std::time::Instant::now();
+    assert_passes(run_script("! exec sleep 60 &\n"));
+    assert!(start.elapsed() < std::time::Duration::from_secs(30));
+
+    // How a killed process exits isn't checked, whatever its prefix
+    for script in [
+        "exec sh -c 'sleep 0.3; echo done' &\nexec echo hi\n",
+        "exec sleep 60 &\n",
+        "exec sh -c 'exit 1' &\n",
+    ] {
+        assert_passes(run_script(script));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_cleanup_kills_the_whole_process_group() {
+    // The grandchild holds the output pipes; only a group kill closes them
+    let start = std::time::Instant::now();
+    assert_passes(run_script("exec sh -c 'sleep 8 & exec sleep 8' &\n"));
+    assert!(
+        start.elapsed() < std::time::Duration::from_secs(4),
+        "Cleanup took {:?}",
+        start.elapsed()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_go_kill_scripts() {
+    for script in ["testdata/kill.txt", "testdata/kill_unnamed.txt"] {
+        assert_passes(run_test(Path::new(script)));
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_interrupt_lets_process_shut_down() {
+    assert_passes(run_script(
+        r#"exec sh -c 'trap "echo caught interrupt; exit 0" INT; touch ready; while :; do sleep 0.01; done' &
+exec sh -c 'while [ ! -e ready ]; do sleep 0.01; done'
+interrupt
+wait
+stdout 'caught interrupt'
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_kill_sends_named_and_numeric_signals() {
+    assert_passes(run_script(
+        r#"! exec sh -c 'trap "echo terminated; exit 3" TERM; touch ready; while :; do sleep 0.01; done' &server&
+exec sh -c 'while [ ! -e ready ]; do sleep 0.01; done'
+kill -TERM server
+wait server
+stdout terminated
+exec echo ${?}
+stdout '^3$'
+
+! exec sleep 10 &sleeper&
+kill -2 sleeper
+wait sleeper
+exec echo ${?}
+stdout '^130$'
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_kill_usage_errors() {
+    assert_fails_with(
+        run_script("! exec sleep 10 &s&\nkill -BOGUS s\n"),
+        "Unsupported signal 'BOGUS'",
+    );
+
+    // Numbers outside the signal range never reach the OS
+    assert_fails_with(
+        run_script("! exec sleep 10 &s&\nkill -999 s\n"),
+        "Invalid use of 'kill': Unsupported signal '999'",
+    );
+
+    assert_fails_with(
+        run_script("kill -KILL missing\n"),
+        "No background process named 'missing'",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_output_of_running_process() {
+    assert_passes(run_script(
+        r#"! exec sh -c 'echo starting; echo warming up >&2; touch ready; exec sleep 10' &server&
+exec sh -c 'while [ ! -e ready ]; do sleep 0.01; done'
+bgoutput server
+stdout '^starting$'
+stderr '^warming up$'
+! stdout stopping
+kill server
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_running_process_has_no_exit_status() {
+    let error_msg = assert_fails_with(
+        run_script(
+            r#"! exec sh -c 'echo starting; exec sleep 10' &server&
+waitoutput server stdout '^starting$' 5s
+stdout listening
+"#,
+        ),
+        "Exit status: still running",
+    );
+    assert_contains(&error_msg, "[stdout]\nstarting\n[still running]");
+    assert_lacks(&error_msg, "Exit status: 0");
+
+    // Once the process has exited, its real status is shown
+    assert_fails_with(
+        run_script(
+            r#"! exec sh -c 'echo done; exit 3' &quick&
+exec sleep 0.3
+bgoutput quick
+stdout listening
+"#,
+        ),
+        "Exit status: 3",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_chatty_process_does_not_block() {
+    // Far more output than a pipe buffer holds, produced before the process
+    // signals that it's ready. Only the last pipe buffer's worth may still be
+    // unread when it does.
+    assert_passes(run_script(
+        r#"! exec sh -c 'i=0; while [ $i -lt 20000 ]; do echo line $i; i=$((i+1)); done; touch ready; exec sleep 10' &chatty&
+exec sh -c 'while [ ! -e ready ]; do sleep 0.01; done'
+bgoutput chatty
+stdout '(?m)^line 10000$'
+kill chatty
+wait chatty
+stdout 'line 19999$'
+"#,
+    ));
+}
+
+#[test]
+fn test_bgoutput_unknown_process() {
+    assert_fails_with(
+        run_script("bgoutput missing\n"),
+        "No background process named 'missing'",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_waitoutput_captures_readiness_line() {
+    assert_passes(run_script(
+        r#"! exec sh -c 'sleep 0.2; echo "listening on 127.0.0.1:4567"; exec sleep 10' &daemon&
+waitoutput daemon stdout 'listening on [\d.]+:(?P<PORT>\d+)' 5s
+stdout '^listening on 127.0.0.1:4567$'
+exec echo port=$PORT
+stdout '^port=4567$'
+kill daemon
+wait daemon
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_waitoutput_waits_for_complete_lines() {
+    assert_passes(run_script(
+        r#"! exec sh -c 'printf "port 80"; sleep 0.5; echo 80; exec sleep 10' &daemon&
+waitoutput daemon stdout 'port (?P<PORT>\d+)' 5s
+exec echo $PORT
+stdout '^8080$'
+"#,
+    ));
+
+    // The last line counts once the process has exited
+    assert_passes(run_script(
+        r#"exec sh -c 'printf ready' &daemon&
+waitoutput daemon stdout '^ready$' 5s
+wait daemon
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_waitoutput_matches_lines_of_stderr() {
+    assert_passes(run_script(
+        r#"! exec sh -c 'echo booting >&2; echo ready >&2; exec sleep 10' &daemon&
+waitoutput daemon stderr '^ready$'
+stderr '^booting\nready$'
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_waitoutput_failures() {
+    let error_msg = assert_fails_with(
+        run_script(
+            r#"! exec sh -c 'echo starting; exec sleep 10' &daemon&
+waitoutput daemon stdout 'listening' 200ms
+"#,
+        ),
+        "The stdout of background process 'daemon' did not match 'listening' within 200ms",
+    );
+    assert_contains(&error_msg, "[stdout]\nstarting");
+
+    assert_fails_with(
+        run_script(
+            r#"! exec sh -c 'echo crashed; exit 3' &daemon&
+waitoutput daemon stdout 'listening'
+"#,
+        ),
+        "Background process 'daemon' exited with exit code 3 before its stdout matched 'listening'",
+    );
+
+    // Without any timeout the wait still gives up rather than hanging
+    let started = std::time::Instant::now();
+    assert_fails_with(
+        run_script(
+            r#"! exec sh -c 'echo starting; exec sleep 30' &daemon&
+waitoutput daemon stdout 'listening'
+"#,
+        ),
+        "The stdout of background process 'daemon' did not match 'listening' within 10s",
+    );
+    assert!(started.elapsed() < std::time::Duration::from_secs(20));
+
+    // A process that never prints a line can be checked with negation
+    assert_passes(run_script(
+        r#"! exec sh -c 'echo starting; exec sleep 10' &daemon&
+! waitoutput daemon stdout 'panic' 100ms
+"#,
+    ));
+
+    assert_fails_with(
+        run_script("waitoutput daemon stdout x 10parsecs\n"),
+        "Invalid duration: 10parsecs",
+    );
+}
@@ -2,7 +2,7 @@
 
 use std::fs;
 use tempfile::TempDir;
-use testscript_rs::run_test;
+use testscript_rs::{run_test, testscript, ScriptOutcome};
 
 #[test]
 fn test_exists_command() {
@@ -137,13 +137,60 @@ exec echo "This should not run""#;
     fs::write(&script_path, script_content).unwrap();
 
     let result = run_test(&script_path);
-    assert!(result.is_err(), "Skip test should have failed");
+    assert_eq!(
+        result.unwrap(),
+        ScriptOutcome::Skipped {
+            reason: "This test should be skipped".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_conditional_skip_passes_run() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    let script_content = r#"# Skipping on some platforms must not fail the run
+[unix] skip "unix only skip"
+[!unix] skip "non-unix skip"
+exec false"#;
+
+    fs::write(testdata_dir.join("skip_run.txt"), script_content).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy()).execute();
+    assert!(
+        result.is_ok(),
+        "Skipped script failed the run: {:?}",
+        result
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_skip_cleans_up_like_a_normal_exit() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("skip.txt");
+    fs::write(
+        &script_path,
+        "exec sh -c 'sleep 8 & exec sleep 8' &\nexec echo right\nstdout wrong\nskip later\n",
+    )
+    .unwrap();
+
+    let start = std::time::Instant::now();
+    let params = testscript_rs::RunParams::new().update_scripts(true);
+    let outcome = testscript_rs::run::run_script(&script_path, &params);
+    assert!(
+        matches!(outcome, Ok(ScriptOutcome::Skipped { .. })),
+        "got: {:?}",
+        outcome
+    );
 
-    let error_msg = result.unwrap_err().to_string();
-    println!("Skip error message: {}", error_msg);
-    // The skip command should create a contextual error showing the skip line
-    assert!(error_msg.contains("skip_test.txt"));
-    assert!(error_msg.contains("skip \"This test should be skipped\""));
+    // Pending updates are written and background processes are killed
+    assert!(fs::read_to_string(&script_path)
+        .unwrap()
+        .contains("stdout right"));
+    assert!(start.elapsed() < std::time::Duration::from_secs(4));
 }
 
 #[test]
@@ -256,14 +303,18 @@ fn test_symlink_error_cases() {
     let temp_dir = TempDir::new().unwrap();
     let script_path = temp_dir.path().join("symlink_error_test.txt");
 
-    let script_content = r#"# Test symlink error cases
-! symlink
-! symlink only_one_arg"#;
+    // Wrong argument counts are usage errors, which `!` doesn't excuse, so
+    // these scripts fail where they used to pass (see CHANGELOG.md)
+    for script_content in ["! symlink", "! symlink only_one_arg"] {
+        fs::write(&script_path, script_content).unwrap();
 
-    fs::write(&script_path, script_content).unwrap();
-
-    let result = run_test(&script_path);
-    assert!(result.is_ok(), "Symlink error test failed: {:?}", result);
+        let error_msg = run_test(&script_path).unwrap_err().to_string();
+        assert!(
+            error_msg.contains("Invalid use of 'symlink'"),
+            "Error: {}",
+            error_msg
+        );
+    }
 }
 
 #[test]
@@ -285,3 +336,26 @@ original content"#;
     let result = run_test(&script_path);
     assert!(result.is_ok(), "Issue example test failed: {:?}", result);
 }
+
+#[test]
+fn test_cmp_against_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("cmp_output_test.txt");
+
+    let script_content = r#"# Test cmp and cmpenv with command output
+exec echo hello world
+cmp stdout expect
+cmpenv stdout expect
+! cmp stdout other
+! cmp stderr expect
+
+-- expect --
+hello world
+-- other --
+goodbye"#;
+
+    fs::write(&script_path, script_content).unwrap();
+
+    let result = run_test(&script_path);
+    assert!(result.is_ok(), "Cmp output test failed: {:?}", result);
+}
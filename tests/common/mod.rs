@@ -0,0 +1,86 @@
+//! Fixtures shared by the integration tests
+//!
+//! Each test binary uses a different subset of these helpers.
+#![allow(dead_code)]
+
+use std::fmt::Debug;
+use std::fs;
+use std::path::PathBuf;
+use tempfile::TempDir;
+use testscript_rs::{run_test, Result, ScriptOutcome};
+
+/// A `testdata` directory holding a single script, removed when dropped
+pub struct Testdata {
+    _temp_dir: TempDir,
+    /// The `testdata` directory, to hand to `testscript::run`
+    pub dir: String,
+    /// The path of the script
+    pub script: PathBuf,
+}
+
+/// Write `content` as the script `name` in a fresh `testdata` directory
+pub fn testdata(name: &str, content: &str) -> Testdata {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+    let script = testdata_dir.join(name);
+    fs::write(&script, content).unwrap();
+    Testdata {
+        dir: testdata_dir.to_string_lossy().into_owned(),
+        script,
+        _temp_dir: temp_dir,
+    }
+}
+
+/// Run `content` as a script with the default parameters
+pub fn run_script(content: &str) -> Result<ScriptOutcome> {
+    run_named_script("script.txt", content)
+}
+
+/// Run `content` as the script `name` with the default parameters
+pub fn run_named_script(name: &str, content: &str) -> Result<ScriptOutcome> {
+    run_test(&testdata(name, content).script)
+}
+
+/// Unwrap the result of a script that should pass, showing the whole error
+/// if it didn't
+#[track_caller]
+pub fn assert_passes<T>(result: Result<T>) -> T {
+    result.unwrap_or_else(|error| panic!("Script failed:\n{}", error))
+}
+
+/// Check that a script failed with an error mentioning `expected`, and
+/// return the error message for further checks
+#[track_caller]
+pub fn assert_fails_with<T: Debug>(result: Result<T>, expected: &str) -> String {
+    match result {
+        Ok(value) => panic!("Script passed with {:?}, expected: {}", value, expected),
+        Err(error) => {
+            let message = error.to_string();
+            assert_contains(&message, expected);
+            message
+        }
+    }
+}
+
+/// Check that an error message mentions `expected`
+#[track_caller]
+pub fn assert_contains(message: &str, expected: &str) {
+    assert!(
+        message.contains(expected),
+        "Expected {:?} in error:\n{}",
+        expected,
+        message
+    );
+}
+
+/// Check that an error message doesn't mention `unexpected`
+#[track_caller]
+pub fn assert_lacks(message: &str, unexpected: &str) {
+    assert!(
+        !message.contains(unexpected),
+        "Unexpected {:?} in error:\n{}",
+        unexpected,
+        message
+    );
+}
@@ -0,0 +1,89 @@
+//! Tests for continue-on-failure mode
+
+use std::fs;
+use tempfile::TempDir;
+use testscript_rs::testscript;
+
+const SCRIPT: &str = r#"# Several independent assertions
+exec echo one
+stdout one
+stdout wrong-one
+
+exists missing.txt
+
+exec echo two
+stdout two
+stdout wrong-two
+"#;
+
+#[test]
+fn test_continue_on_failure_reports_every_failure() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+    fs::write(testdata_dir.join("continue.txt"), SCRIPT).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .continue_on_failure(true)
+        .execute();
+
+    let error_msg = result.unwrap_err().to_string();
+    assert!(error_msg.contains("3 failures"), "got: {}", error_msg);
+    assert!(error_msg.contains("> 4 | stdout wrong-one"));
+    assert!(error_msg.contains("> 6 | exists missing.txt"));
+    assert!(error_msg.contains("> 10 | stdout wrong-two"));
+}
+
+#[test]
+fn test_default_stops_at_first_failure() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+    fs::write(testdata_dir.join("stop.txt"), SCRIPT).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy()).execute();
+
+    let error_msg = result.unwrap_err().to_string();
+    assert!(error_msg.contains("> 4 | stdout wrong-one"));
+    assert!(!error_msg.contains("> 6 | exists missing.txt"));
+}
+
+#[test]
+fn test_continue_on_failure_passing_script() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+    fs::write(
+        testdata_dir.join("pass.txt"),
+        "exec echo hello\nstdout hello\n",
+    )
+    .unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .continue_on_failure(true)
+        .execute();
+    assert!(result.is_ok(), "Passing script failed: {:?}", result);
+}
+
+#[test]
+fn test_skip_after_failure_still_fails() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+    fs::write(
+        testdata_dir.join("skip.txt"),
+        "exists missing.txt\nskip later\n",
+    )
+    .unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .continue_on_failure(true)
+        .execute();
+
+    let error_msg = result.unwrap_err().to_string();
+    assert!(
+        error_msg.contains("> 1 | exists missing.txt"),
+        "got: {}",
+        error_msg
+    );
+}
@@ -0,0 +1,81 @@
+//! Tests for unified diffs in comparison errors
+
+use std::fs;
+use tempfile::TempDir;
+use testscript_rs::run_test;
+
+fn run_failing(name: &str, content: &str) -> String {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join(name);
+    fs::write(&script_path, content).unwrap();
+    run_test(&script_path).unwrap_err().to_string()
+}
+
+#[test]
+fn test_cmp_shows_only_changed_hunk() {
+    let lines: Vec<String> = (1..=200).map(|i| format!("line {}", i)).collect();
+    let mut changed = lines.clone();
+    changed[99] = "line one hundred".to_string();
+
+    let script = format!(
+        "cmp a.txt b.txt\n\n-- a.txt --\n{}\n-- b.txt --\n{}\n",
+        lines.join("\n"),
+        changed.join("\n")
+    );
+    let error_msg = run_failing("big_cmp.txt", &script);
+
+    assert!(
+        error_msg.contains(
+            "--- a.txt\n+++ b.txt\n@@ -97,7 +97,7 @@\n line 97\n line 98\n line 99\n-line 100\n+line one hundred\n line 101\n"
+        ),
+        "Error: {}",
+        error_msg
+    );
+    assert!(!error_msg.contains("line 50"), "Error: {}", error_msg);
+}
+
+#[test]
+fn test_cmpenv_shows_diff_after_substitution() {
+    let error_msg = run_failing(
+        "cmpenv_diff.txt",
+        r#"env NAME=world
+cmpenv got.txt want.txt
+
+-- got.txt --
+header
+hello there
+footer
+-- want.txt --
+header
+hello $NAME
+footer
+"#,
+    );
+
+    assert!(
+        error_msg.contains("+++ want.txt (after substitution)"),
+        "Error: {}",
+        error_msg
+    );
+    assert!(
+        error_msg.contains(" header\n-hello there\n+hello world\n footer"),
+        "Error: {}",
+        error_msg
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_multiline_stdout_mismatch_shows_diff() {
+    let error_msg = run_failing(
+        "stdout_diff.txt",
+        "exec printf 'one\\ntwo\\nthree\\n'\nstdout 'one\\ntwo\\nfour'\n",
+    );
+
+    assert!(
+        error_msg.contains("--- Expected\n+++ Actual\n"),
+        "Error: {}",
+        error_msg
+    );
+    assert!(error_msg.contains("-four\n+three"), "Error: {}", error_msg);
+}
@@ -39,8 +39,12 @@ fn test_parse_malformed_conditions() {
 
     // Test empty condition
     let empty_condition = parser::parse("[] exec echo hello");
-    let script = empty_condition.unwrap();
-    assert_eq!(script.commands[0].condition, Some("".to_string()));
+    assert!(empty_condition.is_err());
+
+    // Test malformed expressions
+    assert!(parser::parse("[unix &&] exec echo hello").is_err());
+    assert!(parser::parse("[(unix] exec echo hello").is_err());
+    assert!(parser::parse("[unix] [linux exec echo hello").is_err());
 }
 
 #[test]
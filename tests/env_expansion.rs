@@ -0,0 +1,109 @@
+//! Tests for environment variable expansion in command arguments
+
+use std::fs;
+use tempfile::TempDir;
+use testscript_rs::run_test;
+
+#[test]
+fn test_exec_args_are_expanded() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("exec_expand.txt");
+
+    let script_content = r#"# Arguments to exec see expanded variables
+env GREETING=hello
+exec echo $GREETING ${GREETING}world
+stdout "hello helloworld"
+
+exec cat $WORK/config.toml
+stdout "key = 1"
+
+-- config.toml --
+key = 1"#;
+
+    fs::write(&script_path, script_content).unwrap();
+
+    let result = run_test(&script_path);
+    assert!(result.is_ok(), "Exec expansion test failed: {:?}", result);
+}
+
+#[test]
+fn test_single_quotes_disable_expansion() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("single_quotes.txt");
+
+    let script_content = r#"# Single-quoted text is passed through literally
+env NAME=value
+exec echo '$NAME' "$NAME"
+stdout '^\$NAME value$'
+
+exec echo $$NAME
+stdout '^\$NAME$'"#;
+
+    fs::write(&script_path, script_content).unwrap();
+
+    let result = run_test(&script_path);
+    assert!(result.is_ok(), "Single quote test failed: {:?}", result);
+}
+
+#[test]
+fn test_builtin_args_are_expanded() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("builtin_expand.txt");
+
+    let script_content = r#"# Builtins such as mkdir, cp, cd and env expand their arguments
+env DIR=nested
+env FILE=source.txt
+mkdir $DIR/deeper
+cp $FILE ${DIR}/deeper/copy.txt
+env COPY=$DIR/deeper/copy.txt
+exists $COPY
+cmp $COPY source.txt
+cd $DIR/deeper
+exec cat copy.txt
+stdout content
+
+-- source.txt --
+content"#;
+
+    fs::write(&script_path, script_content).unwrap();
+
+    let result = run_test(&script_path);
+    assert!(
+        result.is_ok(),
+        "Builtin expansion test failed: {:?}",
+        result
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_special_variables() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("special_vars.txt");
+
+    let script_content = r#"# Go-style special variables are predefined
+mkdir sub
+cp file.txt sub${/}copy$exe
+exists $WORK${/}sub${/}copy
+exec cat sub/copy
+stdout content
+
+exec sh -c 'echo hidden > $0' $devnull
+! stdout hidden
+
+env LIST=a${:}b
+exec echo $LIST
+stdout '^a:b$'
+
+-- file.txt --
+content"#;
+
+    fs::write(&script_path, script_content).unwrap();
+
+    let result = run_test(&script_path);
+    assert!(
+        result.is_ok(),
+        "Special variables test failed: {:?}",
+        result
+    );
+}
@@ -59,3 +59,52 @@ exec echo "after error""#;
     assert!(error_msg.contains("parse_error.txt"));
     assert!(error_msg.contains("unclosed"));
 }
+
+#[cfg(unix)]
+#[test]
+fn test_error_includes_last_command_output() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("last_output.txt");
+
+    let script_content = r#"exec sh -c 'echo "found nothing"; echo "warning: no input" >&2' 'my arg'
+stdout 'found something'
+"#;
+
+    fs::write(&script_path, script_content).unwrap();
+
+    let error_msg = run_test(&script_path).unwrap_err().to_string();
+
+    assert!(
+        error_msg.contains("Last exec: sh -c "),
+        "Error: {}",
+        error_msg
+    );
+    assert!(error_msg.contains("'my arg'"), "Error: {}", error_msg);
+    assert!(
+        error_msg.contains("Working directory: "),
+        "Error: {}",
+        error_msg
+    );
+    assert!(error_msg.contains("Exit status: 0"), "Error: {}", error_msg);
+    assert!(
+        error_msg.contains("[stdout]\nfound nothing"),
+        "Error: {}",
+        error_msg
+    );
+    assert!(
+        error_msg.contains("[stderr]\nwarning: no input"),
+        "Error: {}",
+        error_msg
+    );
+}
+
+#[test]
+fn test_error_without_exec_has_no_last_command() {
+    let temp_dir = TempDir::new().unwrap();
+    let script_path = temp_dir.path().join("no_exec.txt");
+
+    fs::write(&script_path, "exists missing.txt\n").unwrap();
+
+    let error_msg = run_test(&script_path).unwrap_err().to_string();
+    assert!(!error_msg.contains("Last exec"), "Error: {}", error_msg);
+}
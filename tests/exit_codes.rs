@@ -0,0 +1,72 @@
+//! Tests for exit code assertions and reporting
+
+mod common;
+
+use common::{assert_fails_with, assert_passes, run_named_script};
+
+#[cfg(unix)]
+#[test]
+fn test_exec_exit_matches_code() {
+    assert_passes(run_named_script(
+        "exit_ok.txt",
+        r#"exec -exit=2 sh -c 'echo usage >&2; exit 2'
+stderr usage
+exec -exit=0 echo fine
+stdout fine
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec_exit_rejects_other_code() {
+    assert_fails_with(
+        run_named_script("exit_wrong.txt", "exec -exit=2 sh -c 'exit 1'\n"),
+        "ended with exit code 1, expected exit code 2",
+    );
+
+    // Success is not good enough either
+    assert_fails_with(
+        run_named_script("exit_zero.txt", "exec -exit=2 echo hi\n"),
+        "expected exit code 2",
+    );
+}
+
+#[test]
+fn test_exec_exit_rejects_invalid_code() {
+    assert_fails_with(
+        run_named_script("exit_invalid.txt", "exec -exit=abc echo hi\n"),
+        "Invalid exit code in '-exit=abc'",
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_last_exit_code_variable() {
+    assert_passes(run_named_script(
+        "exit_var.txt",
+        r#"! exec sh -c 'exit 3'
+exec echo ${?}
+stdout '^3$'
+exec echo ${?}
+stdout '^0$'
+"#,
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_signal_termination_is_reported() {
+    assert_fails_with(
+        run_named_script("signal.txt", "exec sh -c 'kill -SEGV $$'\n"),
+        "killed by SIGSEGV",
+    );
+
+    assert_passes(run_named_script(
+        "signal_var.txt",
+        r#"! exec sh -c 'kill -TERM $$'
+exec echo ${?}
+stdout '^143$'
+"#,
+    ));
+}
@@ -0,0 +1,88 @@
+//! Tests for hermetic script environments
+
+use std::fs;
+use tempfile::TempDir;
+use testscript_rs::testscript;
+
+#[cfg(unix)]
+#[test]
+fn test_hermetic_clears_host_environment() {
+    std::env::set_var("HERMETIC_TEST_LEAK", "leaked");
+
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    let test_content = r#"# Host variables are not visible to commands
+exec sh -c 'echo "leak=$HERMETIC_TEST_LEAK"'
+stdout '^leak=$'
+
+# Nor are they expanded in the script
+exec echo "[$HERMETIC_TEST_LEAK]"
+stdout '^\[\]$'
+
+# The baseline is seeded
+exec sh -c 'echo $HOME'
+stdout '^/no-home$'
+exec sh -c 'test -d "$TMPDIR" && echo "$TMPDIR"'
+stdout '\.tmp$'
+exec sh -c 'echo $WORK'
+stdout "^$WORK$"
+"#;
+
+    fs::write(testdata_dir.join("hermetic.txt"), test_content).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .hermetic(true)
+        .execute();
+
+    std::env::remove_var("HERMETIC_TEST_LEAK");
+    assert!(result.is_ok(), "Hermetic test failed: {:?}", result);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_hermetic_passthrough() {
+    std::env::set_var("HERMETIC_TEST_ALLOWED", "allowed");
+    std::env::set_var("HERMETIC_TEST_BLOCKED", "blocked");
+
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    let test_content = r#"exec sh -c 'echo "$HERMETIC_TEST_ALLOWED/$HERMETIC_TEST_BLOCKED"'
+stdout '^allowed/$'
+"#;
+
+    fs::write(testdata_dir.join("passthrough.txt"), test_content).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .hermetic(true)
+        .passthrough_env(["HERMETIC_TEST_ALLOWED", "HERMETIC_TEST_UNSET"])
+        .execute();
+
+    std::env::remove_var("HERMETIC_TEST_ALLOWED");
+    std::env::remove_var("HERMETIC_TEST_BLOCKED");
+    assert!(result.is_ok(), "Passthrough test failed: {:?}", result);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_default_mode_inherits_host_environment() {
+    std::env::set_var("HERMETIC_TEST_INHERITED", "inherited");
+
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    let test_content = r#"exec sh -c 'echo $HERMETIC_TEST_INHERITED'
+stdout '^inherited$'
+"#;
+
+    fs::write(testdata_dir.join("inherit.txt"), test_content).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy()).execute();
+
+    std::env::remove_var("HERMETIC_TEST_INHERITED");
+    assert!(result.is_ok(), "Inherit test failed: {:?}", result);
+}
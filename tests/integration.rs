@@ -61,3 +61,31 @@ count-args one two three"#;
 
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_all_scripts_run_after_failure() {
+    use std::fs;
+    use tempfile::TempDir;
+
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    fs::write(testdata_dir.join("a_fails.txt"), "exec false\n").unwrap();
+    fs::write(testdata_dir.join("b_passes.txt"), "exec echo ok\n").unwrap();
+    fs::write(testdata_dir.join("c_fails.txt"), "exists missing.txt\n").unwrap();
+    fs::write(testdata_dir.join("d_skips.txt"), "skip 'not here'\n").unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy()).execute();
+
+    let error_msg = result.unwrap_err().to_string();
+    assert!(error_msg.contains("a_fails.txt"), "got: {}", error_msg);
+    assert!(error_msg.contains("c_fails.txt"), "got: {}", error_msg);
+    assert!(!error_msg.contains("b_passes.txt"), "got: {}", error_msg);
+    assert!(!error_msg.contains("d_skips.txt"), "got: {}", error_msg);
+    assert!(
+        error_msg.ends_with("1 passed, 2 failed, 1 skipped"),
+        "got: {}",
+        error_msg
+    );
+}
@@ -0,0 +1,136 @@
+//! Tests for the `!` and `?` expectation prefixes
+
+mod common;
+
+use common::{assert_fails_with, assert_passes, testdata};
+use testscript_rs::{testscript, Error, TestEnvironment};
+
+fn run_script(content: &str) -> testscript_rs::Result<()> {
+    let testdata = testdata("negation.txt", content);
+    testscript::run(&testdata.dir)
+        .command("fails", |_env: &mut TestEnvironment, _args: &[String]| {
+            Err(Error::Generic("assertion failed".to_string()))
+        })
+        .command("misused", |_env: &mut TestEnvironment, _args: &[String]| {
+            Err(Error::usage_error("misused", "Expected an argument"))
+        })
+        .execute()
+}
+
+#[test]
+fn test_negation_accepts_genuine_failures() {
+    assert_passes(run_script(
+        r#"exec echo hello
+! stdout goodbye
+! exists missing.txt
+! cmp a.txt b.txt
+! fails
+
+-- a.txt --
+a
+-- b.txt --
+b
+"#,
+    ));
+}
+
+#[test]
+fn test_negation_rejects_usage_errors() {
+    let cases = [
+        ("! cmp a.txt", "Invalid use of 'cmp'"),
+        ("exec echo hi\n! stdout", "Invalid use of 'stdout'"),
+        ("exec echo hi\n! stdout 'unclosed('", "Invalid regex"),
+        ("! no-such-command", "Unknown command: no-such-command"),
+        ("[no-such-condition] ! exists x", "Unknown condition"),
+        ("! misused", "Invalid use of 'misused'"),
+        ("! exec echo \"${X:?unset}\"", "X: unset"),
+        (
+            "! wait typo",
+            "Invalid use of 'wait': No background process named 'typo'",
+        ),
+        (
+            "! kill nosuch",
+            "Invalid use of 'kill': No background process named 'nosuch'",
+        ),
+        ("! bgoutput nosuch", "Invalid use of 'bgoutput'"),
+        (
+            "! waitoutput nosuch stdout x",
+            "Invalid use of 'waitoutput'",
+        ),
+    ];
+
+    for (script, expected) in cases {
+        assert_fails_with(run_script(script), expected);
+    }
+}
+
+#[test]
+fn test_is_negatable() {
+    assert!(Error::command_error("exec", "exit code 1").is_negatable());
+    assert!(Error::Generic("failed".to_string()).is_negatable());
+    assert!(!Error::usage_error("cmp", "Expected exactly 2 arguments").is_negatable());
+    assert!(!Error::UnknownCommand {
+        command: "x".to_string()
+    }
+    .is_negatable());
+    assert!(!Error::UnsetVariable {
+        name: "X".to_string(),
+        message: "unset".to_string()
+    }
+    .is_negatable());
+}
+
+#[test]
+fn test_maybe_prefix_allows_either_outcome() {
+    assert_passes(run_script(
+        r#"? exists missing.txt
+? exists present.txt
+? fails
+? exec echo still-runs
+stdout still-runs
+
+-- present.txt --
+here
+"#,
+    ));
+
+    assert_fails_with(run_script("? cmp a.txt"), "Invalid use of 'cmp'");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_skipped_condition_ignores_expectation() {
+    assert_passes(run_script(
+        "[windows] ! exec echo hi
+[windows] ? exec echo hi
+",
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_background_expectations_checked_at_wait() {
+    let passing = [
+        "exec sh -c 'exit 0' &\nwait sh",
+        "! exec sh -c 'exit 1' &\nwait sh",
+        "? exec sh -c 'exit 1' &\nwait sh",
+        "? exec sh -c 'exit 0' &\nwait sh",
+        "! exec sh -c 'exit 1' &",
+    ];
+    for script in passing {
+        assert_passes(run_script(script));
+    }
+
+    assert_fails_with(
+        run_script("exec sh -c 'echo broken >&2; exit 1' &\nwait sh"),
+        "Background process 'sh' (sh -c echo broken >&2; exit 1) failed with exit code 1: broken",
+    );
+
+    assert_fails_with(
+        run_script("! exec sh -c 'exit 0' &\nwait sh"),
+        "was expected to fail but succeeded",
+    );
+
+    // Processes still running at the end of the script are killed unchecked
+    assert_passes(run_script("exec sh -c 'exit 1' &"));
+}
@@ -0,0 +1,74 @@
+//! Tests for parallel script execution
+
+use std::fs;
+use tempfile::TempDir;
+use testscript_rs::{testscript, Outcome};
+
+#[cfg(unix)]
+#[test]
+fn test_scripts_run_concurrently() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    let shared_dir = temp_dir.path().join("shared");
+    fs::create_dir(&testdata_dir).unwrap();
+    fs::create_dir(&shared_dir).unwrap();
+
+    // Each script waits for the other to start, which can only succeed if
+    // both are running at the same time
+    let script = |me: &str, other: &str| {
+        format!(
+            "exec sh -c 'touch $SHARED/{me}; for i in $(seq 100); do [ -f $SHARED/{other} ] && exit 0; sleep 0.05; done; exit 1'\n"
+        )
+    };
+    fs::write(testdata_dir.join("a.txt"), script("a", "b")).unwrap();
+    fs::write(testdata_dir.join("b.txt"), script("b", "a")).unwrap();
+
+    let shared = shared_dir.to_string_lossy().to_string();
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .parallel(2)
+        .setup(move |env| {
+            env.set_env_var("SHARED", &shared);
+            Ok(())
+        })
+        .execute();
+
+    assert!(result.is_ok(), "Parallel scripts failed: {:?}", result);
+}
+
+#[test]
+fn test_parallel_report_is_sorted() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    let names: Vec<String> = (0..12).map(|i| format!("script_{:02}.txt", i)).collect();
+    for (i, name) in names.iter().enumerate() {
+        let content = if i % 3 == 0 {
+            format!("exec echo {}\nstdout wrong\n", i)
+        } else {
+            format!("exec echo {}\nstdout {}\n", i, i)
+        };
+        fs::write(testdata_dir.join(name), content).unwrap();
+    }
+
+    let report = testscript::run(testdata_dir.to_string_lossy())
+        .parallel(4)
+        .execute_with_report()
+        .unwrap();
+
+    let reported: Vec<String> = report
+        .scripts
+        .iter()
+        .map(|s| s.path.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(reported, names);
+
+    for (i, script) in report.scripts.iter().enumerate() {
+        let expected = if i % 3 == 0 {
+            Outcome::Failed
+        } else {
+            Outcome::Passed
+        };
+        assert_eq!(script.outcome, expected, "{}", names[i]);
+    }
+}
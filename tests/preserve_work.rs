@@ -111,17 +111,12 @@ This is test content
         .preserve_work_on_failure(true)
         .execute();
 
-    // Should fail due to skip, but this tests that the preserve logic handles skip correctly
-    assert!(result.is_err());
-    if let Err(e) = result {
-        // The error might be wrapped in script context, so just check for skip-related content
-        let error_str = e.to_string();
-        assert!(
-            error_str.contains("SKIP")
-                || error_str.contains("Test skipped")
-                || error_str.contains("skip")
-        );
-    }
+    // Skipping is not a failure, and the preserve logic must handle it
+    assert!(
+        result.is_ok(),
+        "Skipped script failed the run: {:?}",
+        result
+    );
 }
 
 #[test]
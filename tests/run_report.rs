@@ -0,0 +1,76 @@
+//! Tests for the structured run report
+
+use std::fs;
+use tempfile::TempDir;
+use testscript_rs::{testscript, Outcome};
+
+#[test]
+fn test_execute_with_report() {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    fs::write(
+        testdata_dir.join("a_pass.txt"),
+        "exec echo hello\nstdout hello\n",
+    )
+    .unwrap();
+    fs::write(
+        testdata_dir.join("b_fail.txt"),
+        "exec echo hello\n\nstdout goodbye\n",
+    )
+    .unwrap();
+    fs::write(testdata_dir.join("c_skip.txt"), "skip 'not today'\n").unwrap();
+    fs::write(testdata_dir.join("d_stop.txt"), "stop early\nexec false\n").unwrap();
+
+    let report = testscript::run(testdata_dir.to_string_lossy())
+        .execute_with_report()
+        .unwrap();
+
+    assert_eq!(report.scripts.len(), 4);
+    assert_eq!(report.passed(), 2);
+    assert_eq!(report.failed(), 1);
+    assert_eq!(report.skipped(), 1);
+    assert!(!report.is_success());
+
+    let names: Vec<_> = report
+        .scripts
+        .iter()
+        .map(|s| s.path.file_name().unwrap().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(
+        names,
+        ["a_pass.txt", "b_fail.txt", "c_skip.txt", "d_stop.txt"]
+    );
+
+    let pass = &report.scripts[0];
+    assert_eq!(pass.outcome, Outcome::Passed);
+    assert!(pass.error.is_none());
+    assert_eq!(pass.failing_line, None);
+
+    let fail = &report.scripts[1];
+    assert_eq!(fail.outcome, Outcome::Failed);
+    assert_eq!(fail.failing_line, Some(3));
+    assert!(fail.error.as_ref().unwrap().to_string().contains("goodbye"));
+
+    assert_eq!(
+        report.scripts[2].outcome,
+        Outcome::Skipped {
+            reason: "not today".to_string()
+        }
+    );
+    assert_eq!(
+        report.scripts[3].outcome,
+        Outcome::Stopped {
+            reason: "early".to_string()
+        }
+    );
+}
+
+#[test]
+fn test_execute_with_report_discovery_error() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let result = testscript::run(temp_dir.path().to_string_lossy()).execute_with_report();
+    assert!(result.is_err(), "Expected error when no scripts are found");
+}
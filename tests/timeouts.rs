@@ -0,0 +1,172 @@
+//! Tests for command and script timeouts
+
+mod common;
+
+use common::{assert_contains, assert_fails_with, assert_lacks, assert_passes, testdata};
+use std::time::{Duration, Instant};
+use testscript_rs::testscript;
+
+#[cfg(unix)]
+#[test]
+fn test_command_timeout_kills_hung_process() {
+    let testdata = testdata(
+        "timeout.txt",
+        r#"exec echo before
+exec sh -c 'echo started; sleep 30'
+exec echo after
+"#,
+    );
+
+    let start = Instant::now();
+    let result = testscript::run(&testdata.dir)
+        .command_timeout(Duration::from_millis(300))
+        .execute();
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    let error_msg = assert_fails_with(result, "line 2");
+    assert_contains(&error_msg, "timed out after");
+    assert_contains(&error_msg, "[stdout]\nstarted");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_timeout_kills_process_group() {
+    // The shell's child keeps the output pipe open; killing only the shell
+    // would leave the run waiting for it
+    let testdata = testdata("timeout.txt", "exec sh -c 'sleep 30 & sleep 30'\n");
+
+    let start = Instant::now();
+    let result = testscript::run(&testdata.dir)
+        .command_timeout(Duration::from_millis(300))
+        .execute();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_fails_with(result, "timed out after");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_timeout_covers_output_held_by_grandchild() {
+    // The shell exits at once, but its child keeps stdout open
+    let testdata = testdata(
+        "timeout.txt",
+        r#"timeout -command 300ms
+exec sh -c 'sleep 8 & echo hi'
+"#,
+    );
+
+    let start = Instant::now();
+    let result = testscript::run(&testdata.dir).execute();
+    assert!(
+        start.elapsed() < Duration::from_secs(4),
+        "Took {:?}",
+        start.elapsed()
+    );
+
+    let error_msg = assert_fails_with(result, "line 2");
+    assert_contains(&error_msg, "timed out after");
+    assert_contains(&error_msg, "[stdout]\nhi");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_command_timeout_allows_fast_commands() {
+    let testdata = testdata(
+        "timeout.txt",
+        r#"exec echo fast
+stdout fast
+"#,
+    );
+
+    assert_passes(
+        testscript::run(&testdata.dir)
+            .command_timeout(Duration::from_secs(30))
+            .execute(),
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_timeout() {
+    let testdata = testdata(
+        "timeout.txt",
+        r#"exec sleep 0.2
+exec sleep 0.2
+exec sleep 30
+"#,
+    );
+
+    let start = Instant::now();
+    let result = testscript::run(&testdata.dir)
+        .script_timeout(Duration::from_millis(600))
+        .execute();
+    assert!(start.elapsed() < Duration::from_secs(10));
+
+    let error_msg = assert_fails_with(result, "line 3");
+    assert_contains(&error_msg, "timed out");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_script_timeout_is_one_failure_when_continuing() {
+    let testdata = testdata(
+        "timeout.txt",
+        r#"exec sleep 0.5
+exists a.txt
+exists b.txt
+exec echo never
+"#,
+    );
+
+    let error_msg = assert_fails_with(
+        testscript::run(&testdata.dir)
+            .script_timeout(Duration::from_millis(200))
+            .continue_on_failure(true)
+            .execute(),
+        "Command 'sleep 0.5' timed out after 200ms",
+    );
+    assert_lacks(&error_msg, "at line 2");
+    assert_lacks(&error_msg, "> exists");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_timeout_directive_overrides_builder() {
+    let testdata = testdata(
+        "timeout.txt",
+        r#"# This script needs more time than the default
+timeout -command 30s
+exec sleep 0.5
+exec echo done
+stdout done
+"#,
+    );
+
+    assert_passes(
+        testscript::run(&testdata.dir)
+            .command_timeout(Duration::from_millis(100))
+            .execute(),
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_timeout_directive_sets_script_limit() {
+    let testdata = testdata(
+        "timeout.txt",
+        r#"timeout 300ms
+exec sleep 30
+"#,
+    );
+
+    let start = Instant::now();
+    let result = testscript::run(&testdata.dir).execute();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_fails_with(result, "timed out after");
+}
+
+#[test]
+fn test_timeout_directive_rejects_invalid_duration() {
+    let testdata = testdata("timeout.txt", "timeout forever\n");
+
+    assert_fails_with(testscript::run(&testdata.dir).execute(), "Invalid duration");
+}
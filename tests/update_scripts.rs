@@ -25,6 +25,12 @@ stdout "expected output"
 
     assert!(result.is_ok(), "Update should succeed: {:?}", result);
 
+    // The updated script passes
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .update_scripts(false)
+        .execute();
+    assert!(result.is_ok(), "Updated script should pass: {:?}", result);
+
     // Check that the file was updated with the actual output
     let updated_content = fs::read_to_string(&test_file).unwrap();
     assert!(
@@ -55,6 +61,12 @@ stderr "wrong error message"
 
     assert!(result.is_ok(), "Update should succeed: {:?}", result);
 
+    // The updated script passes
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .update_scripts(false)
+        .execute();
+    assert!(result.is_ok(), "Updated script should pass: {:?}", result);
+
     // Check that the file was updated
     let updated_content = fs::read_to_string(&test_file).unwrap();
     assert!(
@@ -96,6 +108,12 @@ stdout "wrong output"
         result
     );
 
+    // The updated script passes
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .update_scripts(false)
+        .execute();
+    assert!(result.is_ok(), "Updated script should pass: {:?}", result);
+
     // Check that the file was updated
     let updated_content = fs::read_to_string(&test_file).unwrap();
     assert!(
@@ -140,3 +158,239 @@ stdout "expected output"
         "File should not be modified in normal mode"
     );
 }
+
+/// Run `script` in update mode and return the script file's new contents
+fn update_script(script: &str) -> (testscript_rs::Result<()>, String) {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+
+    let test_file = testdata_dir.join("testscript.txt");
+    fs::write(&test_file, script).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .update_scripts(true)
+        .execute();
+    (result, fs::read_to_string(&test_file).unwrap())
+}
+
+/// Update `script`, check the result, then check the updated script passes
+fn update_and_rerun(script: &str) -> String {
+    let temp_dir = TempDir::new().unwrap();
+    let testdata_dir = temp_dir.path().join("testdata");
+    fs::create_dir(&testdata_dir).unwrap();
+    let test_file = testdata_dir.join("testscript.txt");
+    fs::write(&test_file, script).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .update_scripts(true)
+        .execute();
+    assert!(result.is_ok(), "Update should succeed: {:?}", result);
+    let updated = fs::read_to_string(&test_file).unwrap();
+
+    let result = testscript::run(testdata_dir.to_string_lossy())
+        .update_scripts(false)
+        .execute();
+    assert!(
+        result.is_ok(),
+        "Updated script should pass: {:?}\n{}",
+        result,
+        updated
+    );
+    updated
+}
+
+#[test]
+fn test_update_cmp_stdout_archive_file() {
+    let updated = update_and_rerun(
+        "exec echo right\ncmp stdout expect\n\n-- expect --\nwrong\n-- other --\nkept",
+    );
+    assert_eq!(
+        updated,
+        "exec echo right\ncmp stdout expect\n\n-- expect --\nright\n-- other --\nkept"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_cmp_stderr_archive_file() {
+    let updated = update_and_rerun(
+        "exec sh -c 'echo right >&2'\ncmp stderr expect\n\n-- expect --\nwrong\nand more",
+    );
+    assert_eq!(
+        updated,
+        "exec sh -c 'echo right >&2'\ncmp stderr expect\n\n-- expect --\nright"
+    );
+}
+
+#[test]
+fn test_update_cmp_when_actual_is_a_file() {
+    let updated = update_and_rerun("cmp got want\n\n-- got --\nright\n-- want --\nwrong");
+    assert_eq!(
+        updated,
+        "cmp got want\n\n-- got --\nright\n-- want --\nright"
+    );
+}
+
+#[test]
+fn test_update_cmpenv_archive_file() {
+    let updated = update_and_rerun("exec echo one two\ncmpenv stdout expect\n\n-- expect --\none");
+    assert_eq!(
+        updated,
+        "exec echo one two\ncmpenv stdout expect\n\n-- expect --\none two"
+    );
+}
+
+#[test]
+fn test_update_cmp_expected_not_in_archive() {
+    let script = "exec echo right\ncp file expect\ncmp stdout expect\n\n-- file --\nwrong";
+    let (result, updated) = update_script(script);
+    assert!(result.is_err(), "Update should fail: {:?}", result);
+    assert_eq!(updated, script, "Script should be unchanged");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_cmp_refuses_contents_an_archive_cannot_hold() {
+    for (script, reason) in [
+        (
+            "exec echo '-- lookalike --'\ncmp stdout expect\n\n-- expect --\nwrong",
+            "header",
+        ),
+        (
+            "exec printf 'one\\n\\nthree'\ncmp stdout expect\n\n-- expect --\nwrong",
+            "blank lines",
+        ),
+        (
+            "exec sh -c 'printf \"right\\n\" > got'\ncmp got expect\n\n-- expect --\nwrong",
+            "final line ending",
+        ),
+    ] {
+        let (result, updated) = update_script(script);
+        let error = result.unwrap_err().to_string();
+        assert!(
+            error.contains("Update mode can't rewrite 'expect'") && error.contains(reason),
+            "Script {:?} gave error: {}",
+            script,
+            error
+        );
+        assert_eq!(updated, script, "Script should be unchanged");
+    }
+}
+
+#[test]
+fn test_update_cmpenv_keeps_dollar_signs_literal() {
+    let updated = update_and_rerun(
+        "env NAME=x\nexec echo 'cost $5 for $NAME'\ncmpenv stdout expect\n\n-- expect --\nwrong",
+    );
+    assert_eq!(
+        updated,
+        "env NAME=x\nexec echo 'cost $5 for $NAME'\ncmpenv stdout expect\n\n-- expect --\ncost $$5 for $$NAME"
+    );
+}
+
+#[test]
+fn test_update_preserves_layout() {
+    let updated = update_and_rerun(
+        "# check the greeting\n\texec echo hello there\n\t[!windows]  stdout   'bye'  \n\nexists expect\n-- expect --\nx\n",
+    );
+    assert_eq!(
+        updated,
+        "# check the greeting\n\texec echo hello there\n\t[!windows]  stdout   \"hello there\"  \n\nexists expect\n-- expect --\nx\n"
+    );
+}
+
+#[test]
+fn test_update_preserves_crlf_line_endings() {
+    let updated = update_and_rerun(
+        "exec echo right\r\ncmp stdout expect\r\nexec echo two\r\nstdout one\r\n-- expect --\r\nwrong\r\n",
+    );
+    assert_eq!(
+        updated,
+        "exec echo right\r\ncmp stdout expect\r\nexec echo two\r\nstdout two\r\n-- expect --\r\nright\r\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_quotes_special_characters() {
+    let updated = update_and_rerun(
+        r#"exec printf '%s' 'cost: $5.00 (approx) [x] ^y* \d "q"'
+stdout wrong
+exec printf '%s' 'HOME is $HOME'
+stdout wrong
+exec printf '%s' 'tab	and back\slash'
+stdout wrong
+exec printf '%s&' '&job'
+stdout wrong
+"#,
+    );
+    assert_eq!(
+        updated,
+        r#"exec printf '%s' 'cost: $5.00 (approx) [x] ^y* \d "q"'
+stdout '^cost: \$5\.00 \(approx\) \[x\] \^y\* \\\d "q"$'
+exec printf '%s' 'HOME is $HOME'
+stdout '^HOME is \$HOME$'
+exec printf '%s' 'tab	and back\slash'
+stdout "tab\tand back\slash"
+exec printf '%s&' '&job'
+stdout '^&job&$'
+"#
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_moves_multiline_output_to_file() {
+    let updated = update_and_rerun(
+        "exec printf 'one\\ntwo\\n'\n[unix] stdout wrong\nexec sh -c 'printf \"three\\nfour\\n\" >&2'\nstderr wrong\n\n-- stdout.txt --\ntaken\n",
+    );
+    assert_eq!(
+        updated,
+        "exec printf 'one\\ntwo\\n'\n[unix] cmp stdout stdout-2.txt\nexec sh -c 'printf \"three\\nfour\\n\" >&2'\ncmp stderr stderr.txt\n\n-- stdout.txt --\ntaken\n-- stdout-2.txt --\none\ntwo\n-- stderr.txt --\nthree\nfour\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_keeps_unrepresentable_output_inline() {
+    // Blank lines would be lost in an archive file, and a line that looks
+    // like a header would split it
+    let updated = update_and_rerun(
+        "exec printf 'one\\n\\nthree\\n'\nstdout wrong\nexec printf 'a\\n-- b --\\n'\nstdout wrong\n",
+    );
+    assert_eq!(
+        updated,
+        "exec printf 'one\\n\\nthree\\n'\nstdout \"one\\n\\nthree\"\nexec printf 'a\\n-- b --\\n'\nstdout \"a\\n-- b --\"\n"
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_update_anchors_output_that_reads_as_something_else() {
+    let updated = update_and_rerun(
+        r#"exec echo -
+stdout wrong
+exec echo -count=2
+stdout wrong
+exec echo notes
+stdout wrong
+
+-- notes --
+unrelated
+"#,
+    );
+    assert_eq!(
+        updated,
+        r#"exec echo -
+stdout '^-$'
+exec echo -count=2
+stdout '^-count=2$'
+exec echo notes
+stdout '^notes$'
+
+-- notes --
+unrelated
+"#
+    );
+}
@@ -0,0 +1,80 @@
+//! Tests for the Go-compatible execution log
+
+mod common;
+
+use common::{assert_contains, assert_lacks, testdata};
+use testscript_rs::testscript;
+
+const SCRIPT: &str = r#"# comment 1
+exec echo section1
+
+# comment 2
+exec echo section2
+exec sh -c 'echo oops >&2; exit 3'
+
+# comment 3
+exec echo section3
+"#;
+
+fn run_script(verbose: bool, continue_on_failure: bool) -> String {
+    let testdata = testdata("logging.txt", SCRIPT);
+    let result = testscript::run(&testdata.dir)
+        .verbose(verbose)
+        .continue_on_failure(continue_on_failure)
+        .execute();
+
+    // Timings vary from run to run
+    let error_msg = result.unwrap_err().to_string();
+    regex::Regex::new(r"\(\d+\.\d{3}s\)")
+        .unwrap()
+        .replace_all(&error_msg, "(0.000s)")
+        .into_owned()
+}
+
+#[cfg(unix)]
+#[test]
+fn test_failure_log_keeps_failing_section() {
+    let error_msg = run_script(false, false);
+
+    let expected = "# comment 1 (0.000s)\n\
+                    # comment 2 (0.000s)\n\
+                    > exec echo section2\n\
+                    [stdout]\n\
+                    section2\n\
+                    > exec sh -c 'echo oops >&2; exit 3'\n\
+                    [stderr]\n\
+                    oops\n\
+                    [exit status 3]\n\
+                    FAIL: ";
+    assert_contains(&error_msg, expected);
+    assert_contains(&error_msg, "logging.txt:6: ");
+    assert_lacks(&error_msg, "# comment 3 (");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_verbose_log_keeps_every_section() {
+    let error_msg = run_script(true, false);
+
+    let expected = "# comment 1 (0.000s)\n\
+                    > exec echo section1\n\
+                    [stdout]\n\
+                    section1\n\
+                    # comment 2 (0.000s)\n\
+                    > exec echo section2\n";
+    assert_contains(&error_msg, expected);
+}
+
+#[cfg(unix)]
+#[test]
+fn test_continue_log_includes_later_sections() {
+    let quiet = run_script(false, true);
+    assert_contains(&quiet, "# comment 3 (0.000s)");
+    assert_lacks(&quiet, "> exec echo section3");
+
+    let verbose = run_script(true, true);
+    assert_contains(
+        &verbose,
+        "# comment 3 (0.000s)\n> exec echo section3\n[stdout]\nsection3",
+    );
+}